@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::StreamExt;
+use ini::Ini;
+use mysql_async::binlog::events::EventData;
+use mysql_async::binlog::row::BinlogRow;
+
+use crate::cdc::Apply;
+use crate::db::{ColumnDetail, DatabaseMeta, Dialect, Direct};
+use crate::driver::mysql;
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+/// Binlog positions changes have been applied up to, kept next to `conf.ini`.
+const FILE: &str = "binlog.ini";
+
+/// Where in the binlog of the source the next event is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub file: String,
+    pub pos: u64,
+}
+
+impl Position {
+    fn load(id: &str) -> Option<Self> {
+        let conf = Ini::load_from_file(FILE).ok()?;
+        let inf = conf.section(Some(id))?;
+        Some(Self {
+            file: inf.get("file")?.to_string(),
+            pos: inf.get("pos")?.parse::<u64>().ok()?,
+        })
+    }
+
+    /// Rewrites the whole file through a temporary one, like `checkpoint.ini`.
+    fn save(&self, id: &str) -> Result<()> {
+        let mut conf = Ini::load_from_file(FILE).unwrap_or_default();
+        conf.with_section(Some(id))
+            .set("file", self.file.as_str())
+            .set("pos", self.pos.to_string());
+        let tmp = format!("{}.tmp", FILE);
+        conf.write_to_file(&tmp)
+            .and_then(|_| std::fs::rename(&tmp, FILE))
+            .map_err(|e| Error::Checkpoint(format!("{}: {}", FILE, e)))
+    }
+}
+
+/// Progress of `stream`, handed to its caller to report.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// `table` was copied in full before following the binlog
+    Copied { table: String, rows: u64 },
+    /// following the binlog starts at this position
+    Following(Position),
+    /// changes to `table` applied in the transaction ending at `position`
+    Applied {
+        table: String,
+        upserted: u64,
+        deleted: u64,
+        position: Position,
+    },
+    /// the connection dropped and is made again at `position` after `wait`
+    Reconnecting { position: Position, wait: Duration },
+}
+
+/// Server id this process registers with as a replica, `server_id` under
+/// `[BINLOG]`. It must differ from the ids of the source and its other
+/// replicas.
+fn server_id_from_config() -> Result<u32> {
+    let server_id = Ini::load_from_file("conf.ini").ok().and_then(|conf| {
+        conf.section(Some("BINLOG"))?
+            .get("server_id")
+            .map(String::from)
+    });
+    match server_id {
+        Some(v) => match v.trim().parse::<u32>() {
+            Ok(id) if id > 0 => Ok(id),
+            _ => Err(Error::config(
+                "BINLOG",
+                "server_id",
+                format!("`{}` is not a positive number", v.trim()),
+            )),
+        },
+        None => Ok(1001),
+    }
+}
+
+/// Mirrors changes to `tables` on the `direct` side, which must be MySQL, to
+/// the other side by following the binlog as a replica, until the source
+/// closes the stream. Tables are copied in full the first time, after which
+/// the position of the last applied transaction is saved to `binlog.ini`
+/// and a restart continues from there, as does a reconnect after a dropped
+/// connection, retried following `[RETRY]`. Progress goes to `report`.
+pub async fn stream(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    tables: Vec<String>,
+    mut report: impl FnMut(Event),
+) -> Result<()> {
+    let server_id = server_id_from_config()?;
+    if db_meta.dialect(direct) != Dialect::Mysql {
+        return Err(Error::config(
+            direct.to_str(),
+            "databaseType",
+            "binlog streaming needs a MySQL source",
+        ));
+    }
+    let format = db_meta
+        .clone()
+        .query(
+            direct,
+            "SELECT @@binlog_format, @@binlog_row_image".to_string(),
+        )
+        .await?
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+        .iter()
+        .map(|x| x.canonical().unwrap_or_default().to_uppercase())
+        .collect::<Vec<String>>();
+    if format != ["ROW", "FULL"] {
+        return Err(Error::Query(
+            format!(
+                "binlog streaming needs binlog_format=ROW and binlog_row_image=FULL, the source has {}",
+                format.join(" and ")
+            )
+            .into(),
+        ));
+    }
+
+    let id = db_meta.checkpoint_id(direct, &from_ddb, &to_ddb, "*");
+    let mut position = match Position::load(&id) {
+        Some(position) => position,
+        None => {
+            let position = master_status(db_meta.clone(), direct).await?;
+            for table in &tables {
+                let rows = db_meta
+                    .clone()
+                    .transfer_table(direct, from_ddb.clone(), to_ddb.clone(), table.clone())
+                    .await?;
+                report(Event::Copied {
+                    table: table.clone(),
+                    rows,
+                });
+            }
+            position.save(&id)?;
+            position
+        }
+    };
+    report(Event::Following(position.clone()));
+
+    // a dropped connection resumes from the last applied transaction
+    let mut attempt = 0;
+    loop {
+        let e = match follow(
+            &db_meta,
+            direct,
+            &from_ddb,
+            &to_ddb,
+            &tables,
+            server_id,
+            &id,
+            &mut position,
+            &mut attempt,
+            &mut report,
+        )
+        .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        attempt += 1;
+        let wait = db_meta.backoff(attempt, &e).ok_or(e)?;
+        report(Event::Reconnecting {
+            position: position.clone(),
+            wait,
+        });
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Applies binlog events from `position` over one connection to the source,
+/// saving `position` after every transaction, until the source closes the
+/// stream. Changes not yet flushed when it fails are read again on the next
+/// connection.
+#[allow(clippy::too_many_arguments)]
+async fn follow(
+    db_meta: &DatabaseMeta,
+    direct: Direct,
+    from_ddb: &str,
+    to_ddb: &str,
+    tables: &[String],
+    server_id: u32,
+    id: &str,
+    position: &mut Position,
+    attempt: &mut u32,
+    report: &mut impl FnMut(Event),
+) -> Result<()> {
+    let mut applies = HashMap::new();
+    for table in tables {
+        let apply = Apply::new(db_meta.clone(), direct, from_ddb, to_ddb, table).await?;
+        let unsigned = db_meta
+            .clone()
+            .table_detail(direct, from_ddb.to_string(), table.clone())
+            .await?
+            .iter()
+            .map(unsigned_bits)
+            .collect::<Vec<Option<u32>>>();
+        applies.insert(table.to_lowercase(), (table.clone(), apply, unsigned));
+    }
+    let mut target = db_meta.clone().client(direct.toggle()).await?;
+    let res = async {
+        let mut stream = mysql::binlog_stream(
+            db_meta.config(direct),
+            server_id,
+            &position.file,
+            position.pos,
+        )
+        .await?;
+        while let Some(event) = stream.next().await {
+            let event = event?;
+            let commit = match event.read_data()? {
+                Some(EventData::RotateEvent(e)) => {
+                    position.file = e.name().into_owned();
+                    position.pos = e.position();
+                    false
+                }
+                Some(EventData::RowsEvent(rows)) => {
+                    let tme = match stream.get_tme(rows.table_id()) {
+                        Some(tme) if tme.database_name() == from_ddb => tme,
+                        _ => continue,
+                    };
+                    let name = tme.table_name().to_lowercase();
+                    let (table, apply, unsigned) = match applies.get_mut(&name) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    // writes hold only an after image, deletes only a before image
+                    for row in rows.rows(tme) {
+                        let (before, after) = row?;
+                        let before = before
+                            .map(|x| values(x, table, apply, unsigned))
+                            .transpose()?;
+                        let after = after
+                            .map(|x| values(x, table, apply, unsigned))
+                            .transpose()?;
+                        match (before, after) {
+                            (Some(before), None) => apply.delete(target.as_mut(), before).await?,
+                            (before, Some(after)) => {
+                                // a changed key leaves the old row behind otherwise
+                                if let Some(before) = before {
+                                    if apply.key_of(&before) != apply.key_of(&after) {
+                                        apply.delete(target.as_mut(), before).await?;
+                                    }
+                                }
+                                apply.upsert(target.as_mut(), after).await?;
+                            }
+                            (None, None) => {}
+                        }
+                    }
+                    false
+                }
+                Some(EventData::XidEvent(_)) => true,
+                // statements outside a transaction, such as DDL or writes to
+                // MyISAM tables, end with their own query event
+                Some(EventData::QueryEvent(e)) => e.query() != "BEGIN",
+                _ => false,
+            };
+            if commit {
+                position.pos = event.header().log_pos() as u64;
+                for (table, apply, _) in applies.values_mut() {
+                    apply.flush(target.as_mut()).await?;
+                    if apply.upserted + apply.deleted > 0 {
+                        report(Event::Applied {
+                            table: table.clone(),
+                            upserted: apply.upserted,
+                            deleted: apply.deleted,
+                            position: position.clone(),
+                        });
+                        apply.upserted = 0;
+                        apply.deleted = 0;
+                    }
+                }
+                position.save(id)?;
+                *attempt = 0;
+            }
+        }
+        Ok(())
+    }
+    .await;
+    target.close().await?;
+    res
+}
+
+/// Current binlog file and position of the source.
+async fn master_status(db_meta: DatabaseMeta, direct: Direct) -> Result<Position> {
+    // MySQL 8.4 dropped SHOW MASTER STATUS for SHOW BINARY LOG STATUS, which
+    // servers before 8.2 do not know
+    let rows = match db_meta
+        .clone()
+        .query(direct, "SHOW MASTER STATUS".to_string())
+        .await
+    {
+        Ok(rows) => rows,
+        Err(_) => {
+            db_meta
+                .query(direct, "SHOW BINARY LOG STATUS".to_string())
+                .await?
+        }
+    };
+    match rows.first().map(|x| x.as_slice()) {
+        Some([file, pos, ..]) => Ok(Position {
+            file: file.canonical().unwrap_or_default(),
+            pos: pos
+                .canonical()
+                .and_then(|x| x.parse::<u64>().ok())
+                .unwrap_or(4),
+        }),
+        _ => Err(Error::Query(
+            "binary logging is off on the source".to_string().into(),
+        )),
+    }
+}
+
+/// Width in bits of an unsigned integer column. Binlog rows carry no
+/// signedness unless the source sets `binlog_row_metadata=FULL`, so large
+/// values of these columns decode as negative numbers.
+fn unsigned_bits(column: &ColumnDetail) -> Option<u32> {
+    if !column.column_type.to_lowercase().contains("unsigned") {
+        return None;
+    }
+    match column.data_type.to_lowercase().as_str() {
+        "tinyint" => Some(8),
+        "smallint" => Some(16),
+        "mediumint" => Some(24),
+        "int" | "integer" => Some(32),
+        "bigint" => Some(64),
+        _ => None,
+    }
+}
+
+/// Values of a binlog row, which holds every column in table order.
+/// `unsigned` holds the width of every unsigned integer column.
+fn values(
+    row: BinlogRow,
+    table: &str,
+    apply: &Apply,
+    unsigned: &[Option<u32>],
+) -> Result<Vec<Value>> {
+    if row.len() != apply.columns.len() {
+        return Err(Error::Mapping(format!(
+            "binlog rows of {} hold {} columns, expected {}; restart after changing the table",
+            table,
+            row.len(),
+            apply.columns.len()
+        )));
+    }
+    let columns = row.columns();
+    row.unwrap()
+        .into_iter()
+        .zip(columns.iter())
+        .zip(unsigned.iter().chain(std::iter::repeat(&None)))
+        .map(|((value, column), bits)| {
+            let value = mysql_async::Value::try_from(value)
+                .map(|x| Value::from_mysql(x, column))
+                .map_err(|e| Error::Query(Box::new(e)))?;
+            Ok(match (value, bits) {
+                (Value::Int(x), Some(bits)) if x < 0 => Value::UInt(if *bits < 64 {
+                    x as u64 & ((1 << bits) - 1)
+                } else {
+                    x as u64
+                }),
+                (value, _) => value,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ini::Ini;
+use tokio::sync::mpsc;
+
+use crate::db::{DatabaseMeta, Dialect, Direct, BATCH_QUEUE};
+use crate::driver::mssql::MssqlDriver;
+use crate::driver::{Driver, OnConflict};
+use crate::error::{Error, Result};
+use crate::value::{is_number, Value};
+use crate::watermark::Mark;
+
+/// LSNs changes have been applied up to, kept next to `conf.ini`.
+const FILE: &str = "cdc.ini";
+
+/// keys per `DELETE` statement
+pub(crate) const DELETE_BATCH: usize = 500;
+
+/// `__$operation` of a deleted row, and of a row after insert or update
+const DELETED: i64 = 1;
+const INSERTED: i64 = 2;
+const UPDATED: i64 = 4;
+
+/// Capture instances of tables read through SQL Server change data capture.
+/// A table not listed uses the default instance `schema_table`:
+///
+/// ```ini
+/// [CDC]
+/// dbo.Orders=dbo_Orders_v2
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Captures {
+    tables: HashMap<String, String>,
+}
+
+impl Captures {
+    pub fn from_config() -> Self {
+        let mut captures = Self::default();
+        if let Ok(conf) = Ini::load_from_file("conf.ini") {
+            if let Some(inf) = conf.section(Some("CDC")) {
+                for (k, v) in inf.iter() {
+                    captures
+                        .tables
+                        .insert(k.trim().to_lowercase(), v.trim().to_string());
+                }
+            }
+        }
+        captures
+    }
+
+    /// Capture instance of `table`, looked up by its full and its bare name.
+    pub fn get(&self, table: &str) -> String {
+        let lower = table.to_lowercase();
+        let name = lower.rsplit('.').next().unwrap_or_default();
+        match self.tables.get(&lower).or_else(|| self.tables.get(name)) {
+            Some(instance) => instance.clone(),
+            None => {
+                let (schema, name) = Dialect::Mssql.split_table(table);
+                format!("{}_{}", schema, name)
+            }
+        }
+    }
+}
+
+/// Applied LSNs in hex by the same id as checkpoints, saved to `cdc.ini` on
+/// every change. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Bookmarks(Arc<Mutex<HashMap<String, String>>>);
+
+impl Bookmarks {
+    pub fn load() -> Self {
+        let mut map = HashMap::new();
+        if let Ok(conf) = Ini::load_from_file(FILE) {
+            for (id, inf) in conf.iter() {
+                if let (Some(id), Some(lsn)) = (id, inf.get("lsn")) {
+                    map.insert(id.to_string(), lsn.to_string());
+                }
+            }
+        }
+        Self(Arc::new(Mutex::new(map)))
+    }
+
+    pub fn get(&self, id: &str) -> Option<String> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn set(&self, id: &str, lsn: &str) -> Result<()> {
+        let mut map = self.0.lock().unwrap();
+        map.insert(id.to_string(), lsn.to_string());
+        let mut conf = Ini::new();
+        for (id, lsn) in map.iter() {
+            conf.with_section(Some(id.as_str()))
+                .set("lsn", lsn.as_str());
+        }
+        let tmp = format!("{}.tmp", FILE);
+        conf.write_to_file(&tmp)
+            .and_then(|_| std::fs::rename(&tmp, FILE))
+            .map_err(|e| Error::Checkpoint(format!("{}: {}", FILE, e)))
+    }
+}
+
+/// Outcome of one pass over the changes of a table.
+#[derive(Debug, Clone, Default)]
+pub struct Synced {
+    /// rows of the full copy made when the table had no bookmark yet
+    pub copied: Option<u64>,
+    pub upserted: u64,
+    pub deleted: u64,
+    /// LSN the table is now in sync with, in hex
+    pub lsn: String,
+}
+
+impl Synced {
+    pub fn summary(&self) -> String {
+        match self.copied {
+            Some(rows) => format!("{} rows copied", rows),
+            None => format!("{} upserted, {} deleted", self.upserted, self.deleted),
+        }
+    }
+}
+
+/// Brings `table` on the other side of `direct` up to date with the changes
+/// SQL Server captured since the last pass. The first pass copies the whole
+/// table and remembers the LSN it started at; changes made during that copy
+/// are applied again by the next pass, which is harmless as inserts and
+/// updates are written as upserts.
+pub async fn sync_table(
+    db_meta: DatabaseMeta,
+    bookmarks: Bookmarks,
+    captures: Captures,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    table: String,
+) -> Result<Synced> {
+    if db_meta.dialect(direct) != Dialect::Mssql {
+        return Err(Error::config(
+            direct.to_str(),
+            "databaseType",
+            "change data capture needs an MSSQL source",
+        ));
+    }
+    let id = db_meta.checkpoint_id(direct, &from_ddb, &to_ddb, &table);
+    let instance = captures.get(&table);
+    let db = Dialect::Mssql.quote(&from_ddb);
+
+    let max = lsn(db_meta
+        .clone()
+        .query(direct, format!("SELECT {}.sys.fn_cdc_get_max_lsn()", db))
+        .await?)
+    .ok_or_else(|| {
+        Error::config(
+            direct.to_str(),
+            "db",
+            format!("change data capture is not running on {}", from_ddb),
+        )
+    })?;
+
+    let last = match bookmarks.get(&id) {
+        Some(last) => last,
+        None => {
+            let rows = db_meta
+                .transfer_table(direct, from_ddb, to_ddb, table)
+                .await?;
+            bookmarks.set(&id, &max)?;
+            return Ok(Synced {
+                copied: Some(rows),
+                lsn: max,
+                ..Default::default()
+            });
+        }
+    };
+    if last >= max {
+        return Ok(Synced {
+            lsn: last,
+            ..Default::default()
+        });
+    }
+
+    let bounds = db_meta
+        .clone()
+        .query(
+            direct,
+            format!(
+                "SELECT {0}.sys.fn_cdc_get_min_lsn({1}), {0}.sys.fn_cdc_increment_lsn(0x{2})",
+                db,
+                Dialect::Mssql.literal(&instance),
+                last
+            ),
+        )
+        .await?;
+    let (min, from) = match bounds.first().map(|x| x.as_slice()) {
+        Some([min, from]) => (min.canonical(), from.canonical().unwrap_or_default()),
+        _ => (None, String::new()),
+    };
+    // a zero LSN means no such capture instance
+    let min = min
+        .filter(|x| x.chars().any(|c| c != '0'))
+        .ok_or_else(|| Error::config("CDC", &table, format!("no capture instance {}", instance)))?;
+    if from < min {
+        return Err(Error::Checkpoint(format!(
+            "changes of {} since {} were cleaned up, remove it from {} to copy it again",
+            table, last, FILE
+        )));
+    }
+
+    let mut apply = Apply::new(db_meta.clone(), direct, &from_ddb, &to_ddb, &table).await?;
+    let sql_str = format!(
+        "DECLARE @from binary(10) = 0x{}, @to binary(10) = 0x{}; \
+         SELECT [__$operation], {} FROM {}.cdc.{}(@from, @to, N'all') \
+         ORDER BY [__$start_lsn], [__$seqval]",
+        from,
+        max,
+        apply
+            .columns
+            .iter()
+            .map(|x| Dialect::Mssql.quote(x))
+            .collect::<Vec<String>>()
+            .join(", "),
+        db,
+        Dialect::Mssql.quote(&format!("fn_cdc_get_all_changes_{}", instance))
+    );
+
+    // changes since a long outage can be many, so they are streamed in batches
+    let config = db_meta.config(direct);
+    let mut source = MssqlDriver::connect(config)
+        .await
+        .map_err(|e| e.connect(config.label()))?;
+    let mut target = db_meta.clone().client(direct.toggle()).await?;
+    let (tx, mut rx) = mpsc::channel(BATCH_QUEUE);
+    let read = source.read_query(sql_str, db_meta.batch_size(&table), tx);
+    let write = async {
+        while let Some(batch) = rx.recv().await {
+            for mut row in batch {
+                let operation = match row.first() {
+                    Some(Value::Int(x)) => *x,
+                    _ => continue,
+                };
+                row.remove(0);
+                match operation {
+                    DELETED => apply.delete(target.as_mut(), row).await?,
+                    INSERTED | UPDATED => apply.upsert(target.as_mut(), row).await?,
+                    _ => {}
+                }
+            }
+        }
+        apply.flush(target.as_mut()).await
+    };
+    let res = tokio::try_join!(read, write);
+    target.close().await?;
+    Box::new(source).close().await?;
+    res?;
+
+    bookmarks.set(&id, &max)?;
+    Ok(Synced {
+        copied: None,
+        upserted: apply.upserted,
+        deleted: apply.deleted,
+        lsn: max,
+    })
+}
+
+/// Hex of the single LSN a query returned, `None` when it is null.
+fn lsn(rows: Vec<Vec<Value>>) -> Option<String> {
+    rows.into_iter().next()?.into_iter().next()?.canonical()
+}
+
+/// Writes the changes of one table in the order they were made: runs of
+/// inserts and updates as upserts, runs of deletes as `DELETE` by key.
+pub(crate) struct Apply {
+    dialect: Dialect,
+    ddb: String,
+    table: String,
+    /// source columns, in the order changed rows hold them
+    pub columns: Vec<String>,
+    key: Vec<String>,
+    /// positions of the key columns among `columns`
+    key_idx: Vec<usize>,
+    batch_size: usize,
+    upserts: Vec<Vec<Value>>,
+    /// position in `upserts` by key, so a row changed twice is written once
+    positions: HashMap<Vec<Option<String>>, usize>,
+    deletes: Vec<Vec<Value>>,
+    pub upserted: u64,
+    pub deleted: u64,
+}
+
+impl Apply {
+    /// Changes of `table` on the `direct` side, to be written to the other
+    /// side matched by its key columns.
+    pub(crate) async fn new(
+        db_meta: DatabaseMeta,
+        direct: Direct,
+        from_ddb: &str,
+        to_ddb: &str,
+        table: &str,
+    ) -> Result<Self> {
+        let columns = db_meta
+            .clone()
+            .table_detail(direct, from_ddb.to_string(), table.to_string())
+            .await?
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<String>>();
+        let key = db_meta
+            .clone()
+            .key_columns(direct.toggle(), to_ddb, table)
+            .await?;
+        if key.is_empty() {
+            return Err(Error::config(
+                "WRITEMODE",
+                &format!("{}.key", table),
+                format!("{} has no primary key to apply changes by", table),
+            ));
+        }
+        let key_idx = key
+            .iter()
+            .map(|k| {
+                columns
+                    .iter()
+                    .position(|x| x.eq_ignore_ascii_case(k))
+                    .ok_or_else(|| {
+                        Error::Mapping(format!("{}: no key column {} in the source", table, k))
+                    })
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        Ok(Self {
+            dialect: db_meta.dialect(direct.toggle()),
+            ddb: to_ddb.to_string(),
+            table: table.to_string(),
+            columns,
+            key,
+            key_idx,
+            batch_size: db_meta.batch_size(table),
+            upserts: vec![],
+            positions: HashMap::new(),
+            deletes: vec![],
+            upserted: 0,
+            deleted: 0,
+        })
+    }
+
+    /// The key of `row` in canonical form, to tell whether two rows are the
+    /// same one.
+    pub(crate) fn key_of(&self, row: &[Value]) -> Vec<Option<String>> {
+        self.key_idx.iter().map(|&i| row[i].canonical()).collect()
+    }
+
+    pub(crate) async fn upsert(&mut self, target: &mut dyn Driver, row: Vec<Value>) -> Result<()> {
+        self.flush_deletes(target).await?;
+        let key = self.key_of(&row);
+        match self.positions.get(&key) {
+            Some(&idx) => self.upserts[idx] = row,
+            None => {
+                self.positions.insert(key, self.upserts.len());
+                self.upserts.push(row);
+            }
+        }
+        if self.upserts.len() >= self.batch_size {
+            self.flush_upserts(target).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn delete(&mut self, target: &mut dyn Driver, row: Vec<Value>) -> Result<()> {
+        self.flush_upserts(target).await?;
+        self.deletes
+            .push(self.key_idx.iter().map(|&i| row[i].clone()).collect());
+        if self.deletes.len() >= DELETE_BATCH {
+            self.flush_deletes(target).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the changes still held back.
+    pub(crate) async fn flush(&mut self, target: &mut dyn Driver) -> Result<()> {
+        self.flush_upserts(target).await?;
+        self.flush_deletes(target).await
+    }
+
+    async fn flush_upserts(&mut self, target: &mut dyn Driver) -> Result<()> {
+        if self.upserts.is_empty() {
+            return Ok(());
+        }
+        self.positions.clear();
+        let rows = std::mem::take(&mut self.upserts);
+        self.upserted += target
+            .write_rows(
+                &self.ddb,
+                &self.table,
+                &self.columns,
+                rows,
+                &OnConflict::Update(self.key.clone()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn flush_deletes(&mut self, target: &mut dyn Driver) -> Result<()> {
+        if self.deletes.is_empty() {
+            return Ok(());
+        }
+        let keys = std::mem::take(&mut self.deletes);
+        let condition = key_condition(self.dialect, &self.key, &keys);
+        target
+            .execute(&[format!(
+                "DELETE FROM {} WHERE {}",
+                self.dialect.table_name(&self.ddb, &self.table),
+                condition
+            )])
+            .await?;
+        self.deleted += keys.len() as u64;
+        Ok(())
+    }
+}
+
+/// Matches the rows holding any of `keys`, values of the `key` columns.
+pub(crate) fn key_condition(dialect: Dialect, key: &[String], keys: &[Vec<Value>]) -> String {
+    keys.iter()
+        .map(|values| {
+            let terms = key
+                .iter()
+                .zip(values)
+                .map(|(column, value)| match (value, Mark::from_value(value)) {
+                    // text reading as a number, such as `007`, may be a text key
+                    (Value::Text(x), Some(_)) if is_number(x) => {
+                        format!("{} = {}", dialect.quote(column), dialect.literal(x))
+                    }
+                    (_, Some(mark)) => {
+                        format!("{} = {}", dialect.quote(column), mark.literal(dialect))
+                    }
+                    (_, None) => format!("{} IS NULL", dialect.quote(column)),
+                })
+                .collect::<Vec<String>>();
+            format!("({})", terms.join(" AND "))
+        })
+        .collect::<Vec<String>>()
+        .join(" OR ")
+}
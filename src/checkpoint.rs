@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use ini::Ini;
+
+use crate::db::{KeyKind, KeyRange};
+use crate::error::{Error, Result};
+
+/// Progress of running transfers, kept next to `conf.ini`.
+const FILE: &str = "checkpoint.ini";
+
+/// How far one key range of a table has been copied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub range: KeyRange,
+    /// key of the last row written; rows from it on are copied again on resume
+    pub last: Option<i64>,
+    /// rows written below `last`, or in total once `done`
+    pub rows: u64,
+    pub done: bool,
+}
+
+impl Chunk {
+    pub fn new(range: KeyRange) -> Self {
+        Self {
+            range,
+            last: None,
+            rows: 0,
+            done: false,
+        }
+    }
+}
+
+/// How far one table has been copied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// column rows are read in order of; without one a table cannot resume
+    pub key: Option<String>,
+    pub kind: KeyKind,
+    pub chunks: Vec<Chunk>,
+    /// the target held no rows of its own when the copy started, so rows
+    /// past a checkpoint may be deleted and copied again on resume
+    pub owned: bool,
+}
+
+impl Progress {
+    fn parse(inf: &ini::Properties) -> Option<Self> {
+        let count = inf.get("chunks")?.parse::<usize>().ok()?;
+        let mut chunks = Vec::with_capacity(count);
+        for idx in 0..count {
+            let get = |name: &str| inf.get(format!("{}.{}", idx, name));
+            chunks.push(Chunk {
+                range: KeyRange::parse(get("range")?)?,
+                last: get("last").and_then(|x| x.parse::<i64>().ok()),
+                rows: get("rows")?.parse::<u64>().ok()?,
+                done: get("done")? == "true",
+            });
+        }
+        Some(Self {
+            key: inf.get("key").filter(|x| !x.is_empty()).map(String::from),
+            kind: KeyKind::parse(inf.get("kind")?)?,
+            chunks,
+            owned: inf.get("owned") == Some("true"),
+        })
+    }
+
+    fn write(&self, conf: &mut Ini, id: &str) {
+        let mut entries = vec![
+            ("key".to_string(), self.key.clone().unwrap_or_default()),
+            ("kind".to_string(), self.kind.as_str().to_string()),
+            ("chunks".to_string(), self.chunks.len().to_string()),
+            ("owned".to_string(), self.owned.to_string()),
+        ];
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            entries.push((format!("{}.range", idx), chunk.range.to_text()));
+            entries.push((
+                format!("{}.last", idx),
+                chunk.last.map(|x| x.to_string()).unwrap_or_default(),
+            ));
+            entries.push((format!("{}.rows", idx), chunk.rows.to_string()));
+            entries.push((format!("{}.done", idx), chunk.done.to_string()));
+        }
+        for (k, v) in entries {
+            conf.with_section(Some(id)).set(k, v);
+        }
+    }
+}
+
+/// Unfinished table transfers by id, saved to `checkpoint.ini` on every
+/// change so an interrupted copy can pick up where it stopped. Clones share
+/// the same state.
+#[derive(Debug, Clone)]
+pub struct Checkpoints {
+    file: Arc<PathBuf>,
+    map: Arc<Mutex<HashMap<String, Progress>>>,
+}
+
+impl Default for Checkpoints {
+    fn default() -> Self {
+        Self {
+            file: Arc::new(PathBuf::from(FILE)),
+            map: Default::default(),
+        }
+    }
+}
+
+impl Checkpoints {
+    pub fn load() -> Self {
+        Self::load_from(FILE)
+    }
+
+    /// Transfers saved in `file` instead of `checkpoint.ini`.
+    pub(crate) fn load_from(file: impl Into<PathBuf>) -> Self {
+        let file = file.into();
+        let mut map = HashMap::new();
+        if let Ok(conf) = Ini::load_from_file(&file) {
+            for (id, inf) in conf.iter() {
+                if let (Some(id), Some(progress)) = (id, Progress::parse(inf)) {
+                    map.insert(id.to_string(), progress);
+                }
+            }
+        }
+        Self {
+            file: Arc::new(file),
+            map: Arc::new(Mutex::new(map)),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Progress> {
+        self.map.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn start(&self, id: &str, progress: Progress) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+        map.insert(id.to_string(), progress);
+        save(&self.file, &map)
+    }
+
+    /// Records that the rows of chunk `idx` below `last` are in the target.
+    pub fn advance(&self, id: &str, idx: usize, last: i64, rows: u64) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+        if let Some(chunk) = map.get_mut(id).and_then(|x| x.chunks.get_mut(idx)) {
+            chunk.last = Some(last);
+            chunk.rows = rows;
+        }
+        save(&self.file, &map)
+    }
+
+    pub fn finish_chunk(&self, id: &str, idx: usize, rows: u64) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+        if let Some(chunk) = map.get_mut(id).and_then(|x| x.chunks.get_mut(idx)) {
+            chunk.rows = rows;
+            chunk.done = true;
+        }
+        save(&self.file, &map)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+        if map.remove(id).is_some() {
+            save(&self.file, &map)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites the whole file through a temporary one, so a crash while saving
+/// leaves the previous checkpoint intact.
+fn save(file: &Path, map: &HashMap<String, Progress>) -> Result<()> {
+    let fail = |e: std::io::Error| Error::Checkpoint(format!("{}: {}", file.display(), e));
+    if map.is_empty() {
+        if file.exists() {
+            std::fs::remove_file(file).map_err(fail)?;
+        }
+        return Ok(());
+    }
+    let mut conf = Ini::new();
+    for (id, progress) in map {
+        progress.write(&mut conf, id);
+    }
+    let tmp = file.with_extension("ini.tmp");
+    conf.write_to_file(&tmp)
+        .and_then(|_| std::fs::rename(&tmp, file))
+        .map_err(fail)
+}
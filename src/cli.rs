@@ -0,0 +1,610 @@
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use crate::binlog::{self, Event};
+use crate::cdc::{self, Bookmarks, Captures};
+use crate::db::{DatabaseMeta, DeleteCheck, Direct, VerifyMode, WorkerPool, WriteMode};
+use crate::diff;
+use crate::driver;
+use crate::error::Result;
+
+const USAGE: &str = "usage:
+    sql-transfer                                   open the window
+    sql-transfer list-dbs [--side from|to]
+    sql-transfer list-tables [--side from|to] [--db NAME]
+    sql-transfer describe TABLE [--side from|to] [--db NAME]
+    sql-transfer transfer (TABLE... | --all) [--reverse] [--from-db NAME] [--to-db NAME]
+                         [--load-data] [--workers N] [--chunks N] [--chunk-key COLUMN]
+                         [--resume] [--verify off|count|aggregates|hash] [--watermark COLUMN]
+                         [--mode append|truncate|upsert|insert-ignore] [--deletes keys|flag:COLUMN]
+    sql-transfer diff (TABLE... | --all) [--reverse] [--from-db NAME] [--to-db NAME]
+                     [--key COLUMN[,COLUMN]] [--limit N] [--out DIR]
+    sql-transfer cdc (TABLE... | --all) [--reverse] [--from-db NAME] [--to-db NAME]
+                    [--interval SECONDS]
+    sql-transfer binlog (TABLE... | --all) [--reverse] [--from-db NAME] [--to-db NAME]
+
+connections are read from conf.ini; `transfer` copies FROM -> TO unless --reverse;
+every command takes --verbose to print the statements it runs against a database;
+--load-data writes MySQL targets with LOAD DATA LOCAL INFILE, except in upsert mode;
+--workers sets how many tables are copied at once (default from conf.ini);
+--chunks splits each table into N key ranges copied in parallel, by --chunk-key
+or the primary key (--chunk-key alone uses one range per worker);
+--resume continues tables left unfinished in checkpoint.ini instead of starting over;
+--verify compares each copied table with its source (default from conf.ini, else count);
+--watermark copies only rows whose COLUMN grew past the value saved in watermark.ini by
+the last run, for every table instead of those listed under [INCREMENTAL] in conf.ini;
+rows with a null COLUMN are only copied by the first run, and upsert and insert-ignore
+modes read rows equal to the saved value again in case more arrived after it;
+--mode sets how rows already in the target are handled, for every table instead of
+[WRITEMODE] in conf.ini: upsert overwrites and insert-ignore keeps rows with the same key;
+--deletes removes rows deleted at the source from the target after copying, found by keys the
+source no longer has or by a set flag COLUMN, for every table instead of [DELETES] in conf.ini;
+`diff` lists rows only in the source, only in the target and rows that differ, matched by
+--key or the primary key; --limit caps the rows listed per table (default 100) and --out
+writes them to DIR/TABLE.csv;
+`cdc` applies the changes an MSSQL source captured since the LSN saved in cdc.ini, copying
+tables in full the first time; --interval keeps polling for changes every SECONDS;
+`binlog` follows the binlog of a MySQL source as a replica and applies row changes until
+stopped, copying tables in full the first time and saving its position in binlog.ini";
+
+#[derive(Debug, Default)]
+struct Args {
+    command: String,
+    tables: Vec<String>,
+    side: Direct,
+    db: Option<String>,
+    from_db: Option<String>,
+    to_db: Option<String>,
+    reverse: bool,
+    all: bool,
+    load_data: bool,
+    workers: Option<usize>,
+    chunks: Option<usize>,
+    chunk_key: Option<String>,
+    resume: bool,
+    verify: Option<VerifyMode>,
+    watermark: Option<String>,
+    mode: Option<WriteMode>,
+    deletes: Option<DeleteCheck>,
+    key: Vec<String>,
+    limit: Option<usize>,
+    out: Option<String>,
+    interval: Option<u64>,
+    verbose: bool,
+}
+
+impl Args {
+    fn parse(args: Vec<String>) -> std::result::Result<Self, String> {
+        let mut iter = args.into_iter();
+        let mut res = Args {
+            command: iter.next().unwrap_or_default(),
+            ..Default::default()
+        };
+        while let Some(arg) = iter.next() {
+            let mut value = |name: &str| iter.next().ok_or(format!("{} needs a value", name));
+            match arg.as_str() {
+                "--side" => {
+                    res.side = match value("--side")?.to_lowercase().as_str() {
+                        "from" => Direct::FROM,
+                        "to" => Direct::TO,
+                        other => return Err(format!("unknown side `{}`", other)),
+                    }
+                }
+                "--db" => res.db = Some(value("--db")?),
+                "--from-db" => res.from_db = Some(value("--from-db")?),
+                "--to-db" => res.to_db = Some(value("--to-db")?),
+                "--reverse" => res.reverse = true,
+                "--all" => res.all = true,
+                "--load-data" => res.load_data = true,
+                "--resume" => res.resume = true,
+                "--verbose" => res.verbose = true,
+                "--workers" => {
+                    res.workers = match value("--workers")?.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err("--workers needs a positive number".to_string()),
+                    }
+                }
+                "--chunks" => {
+                    res.chunks = match value("--chunks")?.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err("--chunks needs a positive number".to_string()),
+                    }
+                }
+                "--chunk-key" => res.chunk_key = Some(value("--chunk-key")?),
+                "--key" => {
+                    res.key = value("--key")?
+                        .split(',')
+                        .map(|x| x.trim().to_string())
+                        .filter(|x| !x.is_empty())
+                        .collect()
+                }
+                "--limit" => {
+                    res.limit = match value("--limit")?.parse::<usize>() {
+                        Ok(n) => Some(n),
+                        _ => return Err("--limit needs a number".to_string()),
+                    }
+                }
+                "--out" => res.out = Some(value("--out")?),
+                "--interval" => {
+                    res.interval = match value("--interval")?.parse::<u64>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err("--interval needs a positive number".to_string()),
+                    }
+                }
+                "--watermark" => res.watermark = Some(value("--watermark")?),
+                "--verify" => {
+                    let mode = value("--verify")?;
+                    res.verify = match VerifyMode::parse(&mode) {
+                        Some(mode) => Some(mode),
+                        None => return Err(format!("unknown verify mode `{}`", mode)),
+                    }
+                }
+                "--mode" => {
+                    let mode = value("--mode")?;
+                    res.mode = match WriteMode::parse(&mode) {
+                        Some(mode) => Some(mode),
+                        None => return Err(format!("unknown write mode `{}`", mode)),
+                    }
+                }
+                "--deletes" => {
+                    let check = value("--deletes")?;
+                    res.deletes = match DeleteCheck::parse(&check) {
+                        Some(check) => Some(check),
+                        None => return Err(format!("unknown delete check `{}`", check)),
+                    }
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ => res.tables.push(arg),
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Runs one command without opening the window and returns the process exit
+/// code.
+pub async fn run(args: Vec<String>) -> i32 {
+    let args = match Args::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    match execute(args).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+async fn execute(args: Args) -> Result<i32> {
+    // these need no connection, so a missing or broken conf.ini must not stop them
+    match args.command.as_str() {
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return Ok(0);
+        }
+        "list-dbs" | "list-tables" | "describe" | "transfer" | "diff" | "cdc" | "binlog" => {}
+        other => {
+            eprintln!("unknown command `{}`\n\n{}", other, USAGE);
+            return Ok(2);
+        }
+    }
+    driver::set_verbose(args.verbose);
+    let mut db_meta = DatabaseMeta::initial()?;
+    db_meta.set_load_data(args.load_data);
+    db_meta.set_resume(args.resume);
+    if let Some(mode) = args.verify {
+        db_meta.set_verify(mode);
+    }
+    if let Some(column) = args.watermark.clone() {
+        db_meta.set_incremental(column);
+    }
+    if let Some(mode) = args.mode {
+        db_meta.set_write_mode(mode);
+    }
+    if let Some(check) = args.deletes.clone() {
+        db_meta.set_deletes(check);
+    }
+    if let Some(workers) = args.workers {
+        db_meta.set_workers(workers);
+    }
+    if args.chunks.is_some() || args.chunk_key.is_some() {
+        let chunks = args.chunks.unwrap_or_else(|| db_meta.workers());
+        db_meta.set_chunks(chunks, args.chunk_key.clone());
+    }
+    let db = args
+        .db
+        .clone()
+        .unwrap_or_else(|| db_meta.clone().get_default_db(args.side));
+
+    match args.command.as_str() {
+        "list-dbs" => {
+            for name in db_meta.show_dbs(args.side).await? {
+                println!("{}", name);
+            }
+        }
+        "list-tables" => {
+            for name in db_meta.show_tables(args.side, db).await? {
+                println!("{}", name);
+            }
+        }
+        "describe" => {
+            let table = match args.tables.as_slice() {
+                [table] => table.clone(),
+                _ => {
+                    eprintln!("describe takes exactly one table\n\n{}", USAGE);
+                    return Ok(2);
+                }
+            };
+            for column in db_meta.table_detail(args.side, db, table).await? {
+                let mut flags = vec![];
+                if column.primary_key {
+                    flags.push("pk");
+                }
+                if column.identity {
+                    flags.push("identity");
+                }
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    column.name,
+                    column.column_type,
+                    if column.nullable { "null" } else { "not null" },
+                    flags.join(","),
+                    column.default.unwrap_or_default(),
+                    column.comment
+                );
+            }
+        }
+        "transfer" | "diff" | "cdc" | "binlog" => {
+            let direct = if args.reverse { Direct::TO } else { Direct::FROM };
+            let from_db = args
+                .from_db
+                .clone()
+                .unwrap_or_else(|| db_meta.clone().get_default_db(direct));
+            let to_db = args
+                .to_db
+                .clone()
+                .unwrap_or_else(|| db_meta.clone().get_default_db(direct.toggle()));
+            let tables = if args.all {
+                db_meta.clone().show_tables(direct, from_db.clone()).await?
+            } else {
+                args.tables.clone()
+            };
+            if tables.is_empty() {
+                eprintln!(
+                    "{} needs at least one table or --all\n\n{}",
+                    args.command, USAGE
+                );
+                return Ok(2);
+            }
+            if args.command == "diff" {
+                return diff(&args, db_meta, direct, from_db, to_db, tables).await;
+            }
+            if args.command == "cdc" {
+                return sync(&args, db_meta, direct, from_db, to_db, tables).await;
+            }
+            if args.command == "binlog" {
+                let source = from_db.clone();
+                binlog::stream(db_meta, direct, from_db, to_db, tables, |event| {
+                    print_binlog(&source, event)
+                })
+                .await?;
+                return Ok(0);
+            }
+
+            let pool = WorkerPool::new(db_meta.workers());
+            let mut running = tables
+                .into_iter()
+                .map(|table| {
+                    let job = pool.clone().transfer_table(
+                        db_meta.clone(),
+                        direct,
+                        from_db.clone(),
+                        to_db.clone(),
+                        table.clone(),
+                    );
+                    tokio::spawn(async move { (table, job.await) })
+                })
+                .collect::<FuturesUnordered<_>>();
+
+            let mut failed = 0;
+            while let Some(done) = running.next().await {
+                let (table, res) = done?;
+                match res {
+                    Ok(report) => {
+                        let mismatches = report.mismatches.clone().unwrap_or_default();
+                        let mut line = format!("{}\t{} rows", table, report.rows);
+                        if let Some(badge) = report.badge() {
+                            line.push_str(&format!("\t{}", badge));
+                        }
+                        if let Some(watermark) = &report.watermark {
+                            line.push_str(&format!("\tup to {}", watermark));
+                        }
+                        if let Some(deleted) = report.deleted {
+                            line.push_str(&format!("\t{} deleted", deleted));
+                        }
+                        println!("{}", line);
+                        for mismatch in &mismatches {
+                            eprintln!("{}\t{}", table, mismatch);
+                        }
+                        if !mismatches.is_empty() {
+                            failed += 1;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("{}\t{}", table, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                eprintln!("{} table(s) failed or did not match", failed);
+                return Ok(1);
+            }
+        }
+        _ => unreachable!("commands are checked before conf.ini is read"),
+    }
+    Ok(0)
+}
+
+/// Applies captured changes to each table, once or every `--interval`
+/// seconds until stopped. Returns 1 when a single pass failed for any table.
+async fn sync(
+    args: &Args,
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_db: String,
+    to_db: String,
+    tables: Vec<String>,
+) -> Result<i32> {
+    let (bookmarks, captures) = (Bookmarks::load(), Captures::from_config());
+    loop {
+        let mut code = 0;
+        for table in &tables {
+            let res = cdc::sync_table(
+                db_meta.clone(),
+                bookmarks.clone(),
+                captures.clone(),
+                direct,
+                from_db.clone(),
+                to_db.clone(),
+                table.clone(),
+            )
+            .await;
+            match res {
+                Ok(synced) => println!("{}\t{}\tat 0x{}", table, synced.summary(), synced.lsn),
+                Err(e) => {
+                    eprintln!("{}\t{}", table, e);
+                    code = 1;
+                }
+            }
+        }
+        match args.interval {
+            Some(secs) => tokio::time::sleep(std::time::Duration::from_secs(secs)).await,
+            None => return Ok(code),
+        }
+    }
+}
+
+/// Prints the progress of following the binlog of the `from_db` database.
+fn print_binlog(from_db: &str, event: Event) {
+    match event {
+        Event::Copied { table, rows } => println!("{}\t{} rows copied", table, rows),
+        Event::Following(at) => println!("following {} from {}:{}", from_db, at.file, at.pos),
+        Event::Applied {
+            table,
+            upserted,
+            deleted,
+            position: at,
+        } => println!(
+            "{}\t{} upserted, {} deleted\tat {}:{}",
+            table, upserted, deleted, at.file, at.pos
+        ),
+        Event::Reconnecting { position: at, wait } => eprintln!(
+            "binlog: reconnecting at {}:{} in {:?}",
+            at.file, at.pos, wait
+        ),
+    }
+}
+
+/// Compares each table with its copy, returning 1 when any of them differ.
+async fn diff(
+    args: &Args,
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_db: String,
+    to_db: String,
+    tables: Vec<String>,
+) -> Result<i32> {
+    if let Some(dir) = &args.out {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut code = 0;
+    for table in tables {
+        let res = diff::diff_table(
+            db_meta.clone(),
+            direct,
+            from_db.clone(),
+            to_db.clone(),
+            table.clone(),
+            args.key.clone(),
+            args.limit.unwrap_or(100),
+        )
+        .await;
+        let table_diff = match res {
+            Ok(table_diff) => table_diff,
+            Err(e) => {
+                eprintln!("{}\t{}", table, e);
+                code = 1;
+                continue;
+            }
+        };
+        println!("{}\t{}", table, table_diff.summary());
+        if table_diff.is_empty() {
+            continue;
+        }
+        code = 1;
+        match &args.out {
+            Some(dir) => {
+                let path = std::path::Path::new(dir).join(format!("{}.csv", table));
+                table_diff.write_csv(&path)?;
+                println!("{}\twrote {}", table, path.display());
+            }
+            None => {
+                for row in &table_diff.rows {
+                    println!("{}\t{}", table, table_diff.describe(row));
+                }
+            }
+        }
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> std::result::Result<Args, String> {
+        Args::parse(args.split_whitespace().map(String::from).collect())
+    }
+
+    #[test]
+    fn parses_tables_and_options() {
+        let args = parse("transfer Orders Items --reverse --from-db a").unwrap();
+        assert_eq!(args.command, "transfer");
+        assert_eq!(args.tables, ["Orders", "Items"]);
+        assert!(args.reverse && !args.all);
+        assert_eq!(args.from_db.as_deref(), Some("a"));
+        assert_eq!(args.to_db, None);
+        assert!(matches!(
+            parse("describe t --side TO").unwrap().side,
+            Direct::TO
+        ));
+        assert!(matches!(parse("describe t").unwrap().side, Direct::FROM));
+        assert!(parse("transfer --all").unwrap().all);
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        let err = |args: &str| parse(args).unwrap_err();
+        assert_eq!(err("list-tables --db"), "--db needs a value");
+        assert_eq!(err("list-dbs --side both"), "unknown side `both`");
+        assert_eq!(err("transfer --force"), "unknown option `--force`");
+    }
+
+    #[test]
+    fn parses_load_data() {
+        assert!(parse("transfer t --load-data").unwrap().load_data);
+        assert!(!parse("transfer t").unwrap().load_data);
+    }
+
+    #[test]
+    fn parses_workers() {
+        assert_eq!(
+            parse("transfer --all --workers 4").unwrap().workers,
+            Some(4)
+        );
+        assert_eq!(parse("transfer --all").unwrap().workers, None);
+        for bad in ["0", "-1", "four"] {
+            assert_eq!(
+                parse(&format!("transfer --all --workers {}", bad)).unwrap_err(),
+                "--workers needs a positive number"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_chunks() {
+        let args = parse("transfer t --chunks 8 --chunk-key Id").unwrap();
+        assert_eq!(args.chunks, Some(8));
+        assert_eq!(args.chunk_key.as_deref(), Some("Id"));
+        assert_eq!(
+            parse("transfer t --chunks 0").unwrap_err(),
+            "--chunks needs a positive number"
+        );
+    }
+
+    #[test]
+    fn parses_resume() {
+        assert!(parse("transfer --all --resume").unwrap().resume);
+    }
+
+    #[test]
+    fn parses_verify() {
+        assert_eq!(
+            parse("transfer t --verify hash").unwrap().verify,
+            Some(VerifyMode::Hash)
+        );
+        assert_eq!(
+            parse("transfer t --verify crc").unwrap_err(),
+            "unknown verify mode `crc`"
+        );
+    }
+
+    #[test]
+    fn parses_diff_options() {
+        let args = parse("diff --all --key id,,region --limit 0 --out out").unwrap();
+        assert!(args.all);
+        assert_eq!(args.key, ["id", "region"]);
+        assert_eq!(args.limit, Some(0));
+        assert_eq!(args.out.as_deref(), Some("out"));
+        assert_eq!(
+            parse("diff t --limit x").unwrap_err(),
+            "--limit needs a number"
+        );
+    }
+
+    #[test]
+    fn parses_watermark() {
+        assert_eq!(
+            parse("transfer t --watermark UpdatedAt")
+                .unwrap()
+                .watermark
+                .as_deref(),
+            Some("UpdatedAt")
+        );
+    }
+
+    #[test]
+    fn parses_write_mode() {
+        assert_eq!(
+            parse("transfer t --mode insert-ignore").unwrap().mode,
+            Some(WriteMode::InsertIgnore)
+        );
+        assert_eq!(
+            parse("transfer t --mode replace").unwrap_err(),
+            "unknown write mode `replace`"
+        );
+    }
+
+    #[test]
+    fn parses_interval() {
+        assert_eq!(parse("cdc --all --interval 30").unwrap().interval, Some(30));
+        assert_eq!(
+            parse("cdc --all --interval").unwrap_err(),
+            "--interval needs a value"
+        );
+        assert_eq!(
+            parse("cdc --all --interval 0").unwrap_err(),
+            "--interval needs a positive number"
+        );
+    }
+
+    #[test]
+    fn parses_deletes() {
+        assert_eq!(
+            parse("transfer t --deletes flag:IsDeleted")
+                .unwrap()
+                .deletes,
+            Some(DeleteCheck::Flag("IsDeleted".to_string()))
+        );
+        assert_eq!(
+            parse("transfer t --deletes flag:").unwrap_err(),
+            "unknown delete check `flag:`"
+        );
+    }
+}
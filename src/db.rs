@@ -1,23 +1,33 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime};
 use ini::Ini;
-use mysql_async::{
-    prelude::{FromRow, FromValue, Queryable},
-    Conn, Opts, Pool,
-};
-use tiberius::{AuthMethod, Client, Config};
-use tokio::net::TcpStream;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
-#[derive(Debug, Clone, Copy)]
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
+
+use crate::cdc::{key_condition, DELETE_BATCH};
+use crate::checkpoint::{Checkpoints, Chunk, Progress};
+use crate::ddl;
+use crate::diff;
+use crate::driver::{self, Driver, Filter, OnConflict};
+use crate::error::{Error, Result};
+use crate::value::Value;
+use crate::watermark::{Mark, Watermark, Watermarks};
+
+/// Batches buffered between reader and writer.
+pub(crate) const BATCH_QUEUE: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
 pub enum Direct {
+    #[default]
     FROM,
     TO,
 }
 
-impl Default for Direct {
-    fn default() -> Self {
-        Direct::FROM
-    }
-}
-
 impl Direct {
     pub fn toggle(self) -> Self {
         match self {
@@ -27,275 +37,2947 @@ impl Direct {
     }
 }
 
-pub enum SQLClient {
-    Mysql((Conn, Pool)),
-    Mssql(tiberius::Client<tokio_util::compat::Compat<tokio::net::TcpStream>>),
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Mysql,
+    Mssql,
+    Postgres,
+    Sqlite,
+}
+
+impl Dialect {
+    pub fn quote(self, ident: &str) -> String {
+        match self {
+            Dialect::Mysql => format!("`{}`", ident.replace('`', "``")),
+            Dialect::Mssql => format!("[{}]", ident.replace(']', "]]")),
+            Dialect::Postgres | Dialect::Sqlite => format!("\"{}\"", ident.replace('"', "\"\"")),
+        }
+    }
+
+    pub fn literal(self, value: &str) -> String {
+        match self {
+            Dialect::Mysql => format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''")),
+            Dialect::Mssql => format!("N'{}'", value.replace('\'', "''")),
+            Dialect::Postgres | Dialect::Sqlite => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+
+    /// `COUNT(arg)`, as a 64-bit count on MSSQL.
+    pub fn count(self, arg: &str) -> String {
+        match self {
+            Dialect::Mssql => format!("COUNT_BIG({})", arg),
+            _ => format!("COUNT({})", arg),
+        }
+    }
+
+    /// Splits a `schema.table` name as listed by `show_tables`. MySQL has no
+    /// schemas inside a database and PostgreSQL lists schemas as databases,
+    /// so the schema is only kept for MSSQL.
+    pub fn split_table(self, table: &str) -> (&str, &str) {
+        match (self, table.split_once('.')) {
+            (Dialect::Mssql, Some((schema, name))) => (schema, name),
+            (Dialect::Mssql, None) => ("dbo", table),
+            (_, Some((_, name))) => ("", name),
+            (_, None) => ("", table),
+        }
+    }
+
+    /// Name `table` gets when it is created on this side.
+    pub fn local_table(self, table: &str) -> String {
+        match self.split_table(table) {
+            ("", name) => name.to_string(),
+            (schema, name) => format!("{}.{}", schema, name),
+        }
+    }
+
+    /// Fully qualified name of `table` inside database `ddb`.
+    pub fn table_name(self, ddb: &str, table: &str) -> String {
+        let (schema, name) = self.split_table(table);
+        match self {
+            Dialect::Mysql | Dialect::Postgres | Dialect::Sqlite => {
+                format!("{}.{}", self.quote(ddb), self.quote(name))
+            }
+            Dialect::Mssql => format!(
+                "{}.{}.{}",
+                self.quote(ddb),
+                self.quote(schema),
+                self.quote(name)
+            ),
+        }
+    }
 }
 
 impl Direct {
-    fn to_str(self) -> &'static str {
+    pub(crate) fn to_str(self) -> &'static str {
         match self {
             Direct::FROM => "FROM",
             Direct::TO => "TO",
         }
     }
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DatabaseConfig {
-    class: String,
-    host: String,
-    port: String,
-    user: String,
-    password: String,
-    default_db: String,
+    pub class: String,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub default_db: String,
+    /// schema used as the database for POSTGRES
+    pub schema: String,
+    /// database file for SQLITE
+    pub path: String,
+    /// load MYSQL targets with `LOAD DATA LOCAL INFILE`
+    pub load_data: bool,
+}
+
+impl DatabaseConfig {
+    pub fn from_config(direct: Direct) -> Result<Self> {
+        let section = direct.to_str();
+        let conf =
+            Ini::load_from_file("conf.ini").map_err(|e| Error::config("", "", e.to_string()))?;
+        let inf = conf
+            .section(Some(section))
+            .ok_or_else(|| Error::config(section, "", "section missing"))?;
+        let class = inf
+            .get("databaseType")
+            .ok_or_else(|| Error::config(section, "databaseType", "missing"))?;
+        let default_port = match class {
+            "MSSQL" => 1433,
+            "MYSQL" => 3306,
+            "POSTGRES" => 5432,
+            "SQLITE" => 0,
+            other => {
+                return Err(Error::config(
+                    section,
+                    "databaseType",
+                    format!("`{}` is not one of MSSQL, MYSQL, POSTGRES, SQLITE", other),
+                ))
+            }
+        };
+        let port = match inf.get("port").map(str::trim) {
+            Some(port) if !port.is_empty() => port.parse::<u16>().map_err(|_| {
+                Error::config(section, "port", format!("`{}` is not a port number", port))
+            })?,
+            _ => default_port,
+        };
+        let required = match class {
+            "SQLITE" => "path",
+            _ => "host",
+        };
+        if inf.get(required).unwrap_or_default().trim().is_empty() {
+            return Err(Error::config(section, required, "missing"));
+        }
+        Ok(Self {
+            class: class.to_string(),
+            host: inf.get("host").unwrap_or_default().to_string(),
+            port,
+            user: inf.get("user").unwrap_or_default().to_string(),
+            password: inf.get("password").unwrap_or_default().to_string(),
+            default_db: inf.get("db").unwrap_or_default().to_string(),
+            schema: inf.get("schema").unwrap_or("public").to_string(),
+            path: inf.get("path").unwrap_or_default().to_string(),
+            load_data: false,
+        })
+    }
+
+    /// Where this side points, for telling checkpoints apart and in errors.
+    pub(crate) fn label(&self) -> String {
+        match self.class.as_str() {
+            "SQLITE" => format!("sqlite://{}", self.path),
+            class => format!(
+                "{}://{}:{}/{}",
+                class.to_lowercase(),
+                self.host,
+                self.port,
+                self.default_db
+            ),
+        }
+    }
+}
+/// A column as described by `DatabaseMeta::table_detail`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnDetail {
+    pub name: String,
+    /// bare type name, e.g. `nvarchar`
+    pub data_type: String,
+    /// full declaration, e.g. `nvarchar(max)` or `int(10) unsigned`
+    pub column_type: String,
+    /// characters for text types, bytes for binary types, -1 for MSSQL `max`
+    pub max_length: Option<i64>,
+    pub precision: Option<i64>,
+    /// digits after the point for exact numerics, fractional seconds for temporal types
+    pub scale: Option<i64>,
+    pub nullable: bool,
+    pub default: Option<String>,
+    pub identity: bool,
+    /// computed from other columns, so it cannot be written
+    pub computed: bool,
+    pub primary_key: bool,
+    pub comment: String,
+}
+
+impl ColumnDetail {
+    pub fn mssql_type(&self) -> String {
+        let t = self.data_type.as_str();
+        match (t, self.max_length, self.precision, self.scale) {
+            (_, Some(-1), _, _) => format!("{}(max)", t),
+            (_, Some(len), _, _) => format!("{}({})", t, len),
+            ("decimal" | "numeric", _, Some(p), Some(s)) => format!("{}({},{})", t, p, s),
+            ("datetime2" | "time" | "datetimeoffset", _, _, Some(s)) => format!("{}({})", t, s),
+            _ => t.to_string(),
+        }
+    }
+}
+
+/// Column type conversion between dialects. Defaults cover the common types
+/// of each backend and can be overridden in `conf.ini`:
+///
+/// ```ini
+/// [TYPEMAP]
+/// datetime2=datetime(3)
+/// nvarchar(max)=mediumtext
+/// varchar=varchar({len})
+/// decimal=decimal({precision},{scale})
+///
+/// [COLUMNMAP]
+/// Orders.Remark=text
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TypeMap {
+    types: HashMap<String, String>,
+    columns: HashMap<String, String>,
+}
+
+impl TypeMap {
+    pub fn from_config() -> Self {
+        let mut map = Self::default();
+        if let Ok(conf) = Ini::load_from_file("conf.ini") {
+            if let Some(inf) = conf.section(Some("TYPEMAP")) {
+                for (k, v) in inf.iter() {
+                    map.types.insert(k.trim().to_lowercase(), v.trim().to_string());
+                }
+            }
+            if let Some(inf) = conf.section(Some("COLUMNMAP")) {
+                for (k, v) in inf.iter() {
+                    map.columns.insert(k.trim().to_lowercase(), v.trim().to_string());
+                }
+            }
+        }
+        map
+    }
+
+    /// Target type for `column` of `table`, or a description of why it cannot
+    /// be converted without losing data.
+    pub fn map(
+        &self,
+        from: Dialect,
+        to: Dialect,
+        table: &str,
+        column: &ColumnDetail,
+    ) -> std::result::Result<String, String> {
+        let fill = |t: &String| {
+            let opt = |x: Option<i64>| x.map(|x| x.to_string()).unwrap_or_default();
+            t.replace("{len}", &opt(column.max_length))
+                .replace("{precision}", &opt(column.precision))
+                .replace("{scale}", &opt(column.scale))
+        };
+
+        if let Some(t) = self
+            .columns
+            .get(&format!("{}.{}", table, column.name).to_lowercase())
+        {
+            return Ok(fill(t));
+        }
+        if let Some(t) = self
+            .types
+            .get(&column.column_type)
+            .or_else(|| self.types.get(&column.data_type))
+        {
+            return Ok(fill(t));
+        }
+
+        let mapped = match (from, to) {
+            (Dialect::Mssql, Dialect::Mysql) => mssql_to_mysql(column),
+            (Dialect::Mysql, Dialect::Mssql) => mysql_to_mssql(column),
+            (Dialect::Mssql, Dialect::Postgres) => mssql_to_postgres(column),
+            (Dialect::Mysql, Dialect::Postgres) => mysql_to_postgres(column),
+            (Dialect::Postgres, Dialect::Mysql) => postgres_to_mysql(column),
+            (Dialect::Postgres, Dialect::Mssql) => postgres_to_mssql(column),
+            // sqlite tables are created with mysql type names, which sqlite
+            // accepts and which read back unchanged
+            (Dialect::Mssql, Dialect::Sqlite) => mssql_to_mysql(column),
+            (Dialect::Mysql, Dialect::Sqlite) => Some(mysql_to_sqlite(column)),
+            (Dialect::Postgres, Dialect::Sqlite) => postgres_to_mysql(column),
+            (Dialect::Sqlite, Dialect::Mysql) => sqlite_as_mysql(column).map(|x| x.column_type),
+            (Dialect::Sqlite, Dialect::Mssql) => {
+                sqlite_as_mysql(column).and_then(|x| mysql_to_mssql(&x))
+            }
+            (Dialect::Sqlite, Dialect::Postgres) => {
+                sqlite_as_mysql(column).and_then(|x| mysql_to_postgres(&x))
+            }
+            _ => Some(column.column_type.clone()),
+        };
+        mapped.ok_or_else(|| {
+            format!(
+                "{}.{}: cannot map {:?} type {} to {:?}, set one under [COLUMNMAP] or [TYPEMAP]",
+                table, column.name, from, column.column_type, to
+            )
+        })
+    }
+}
+
+fn mssql_to_mysql(column: &ColumnDetail) -> Option<String> {
+    let len = column.max_length.unwrap_or(0);
+    let fsp = column.scale.unwrap_or(0).min(6);
+    let t = match column.data_type.as_str() {
+        "bit" => "tinyint(1)".to_string(),
+        "tinyint" => "tinyint unsigned".to_string(),
+        "smallint" | "int" | "bigint" | "date" => column.data_type.clone(),
+        "real" => "float".to_string(),
+        "float" => "double".to_string(),
+        "decimal" | "numeric" => match (column.precision, column.scale) {
+            (Some(p), Some(s)) if p <= 65 => format!("decimal({},{})", p, s),
+            _ => return None,
+        },
+        "money" => "decimal(19,4)".to_string(),
+        "smallmoney" => "decimal(10,4)".to_string(),
+        "char" | "nchar" if (1..=255).contains(&len) => format!("char({})", len),
+        "varchar" | "nvarchar" | "char" | "nchar" if (1..=16383).contains(&len) => {
+            format!("varchar({})", len)
+        }
+        "varchar" | "nvarchar" | "char" | "nchar" | "text" | "ntext" | "xml" => {
+            "longtext".to_string()
+        }
+        "binary" if (1..=255).contains(&len) => format!("binary({})", len),
+        "varbinary" | "binary" if (1..=65535).contains(&len) => format!("varbinary({})", len),
+        "varbinary" | "binary" | "image" => "longblob".to_string(),
+        "time" => format!("time({})", fsp),
+        "smalldatetime" => "datetime".to_string(),
+        "datetime" => "datetime(3)".to_string(),
+        "datetime2" | "datetimeoffset" => format!("datetime({})", fsp),
+        "uniqueidentifier" => "char(36)".to_string(),
+        "timestamp" | "rowversion" => "binary(8)".to_string(),
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn mysql_to_mssql(column: &ColumnDetail) -> Option<String> {
+    let len = column.max_length.unwrap_or(0);
+    let fsp = column.scale.unwrap_or(0);
+    let unsigned = column.column_type.contains("unsigned");
+    let t = match (column.data_type.as_str(), unsigned) {
+        ("tinyint", true) => "tinyint".to_string(),
+        ("tinyint", false) => "smallint".to_string(),
+        ("smallint", true) | ("mediumint", _) => "int".to_string(),
+        ("int", true) => "bigint".to_string(),
+        ("bigint", true) => "decimal(20,0)".to_string(),
+        ("smallint" | "int" | "bigint" | "date", _) => column.data_type.clone(),
+        ("float", _) => "real".to_string(),
+        ("double", _) => "float".to_string(),
+        ("decimal", _) => match (column.precision, column.scale) {
+            (Some(p), Some(s)) if p <= 38 => format!("decimal({},{})", p, s),
+            _ => return None,
+        },
+        ("char", _) if (1..=4000).contains(&len) => format!("nchar({})", len),
+        ("varchar" | "char" | "enum" | "set", _) if (1..=4000).contains(&len) => {
+            format!("nvarchar({})", len)
+        }
+        ("varchar" | "char" | "tinytext" | "text" | "mediumtext" | "longtext" | "json", _) => {
+            "nvarchar(max)".to_string()
+        }
+        ("binary", _) if (1..=8000).contains(&len) => format!("binary({})", len),
+        ("varbinary", _) if (1..=8000).contains(&len) => format!("varbinary({})", len),
+        ("binary" | "varbinary" | "tinyblob" | "blob" | "mediumblob" | "longblob", _) => {
+            "varbinary(max)".to_string()
+        }
+        ("datetime" | "timestamp", _) => format!("datetime2({})", fsp),
+        ("time", _) => format!("time({})", fsp),
+        ("year", _) => "smallint".to_string(),
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn mssql_to_postgres(column: &ColumnDetail) -> Option<String> {
+    let len = column.max_length.unwrap_or(0);
+    let fsp = column.scale.unwrap_or(0).min(6);
+    let t = match column.data_type.as_str() {
+        "bit" => "boolean".to_string(),
+        "tinyint" | "smallint" => "smallint".to_string(),
+        "int" => "integer".to_string(),
+        "bigint" | "date" => column.data_type.clone(),
+        "real" => "real".to_string(),
+        "float" => "double precision".to_string(),
+        "decimal" | "numeric" => match (column.precision, column.scale) {
+            (Some(p), Some(s)) => format!("numeric({},{})", p, s),
+            _ => "numeric".to_string(),
+        },
+        "money" => "numeric(19,4)".to_string(),
+        "smallmoney" => "numeric(10,4)".to_string(),
+        "char" | "nchar" if len > 0 => format!("char({})", len),
+        "varchar" | "nvarchar" if len > 0 => format!("varchar({})", len),
+        "varchar" | "nvarchar" | "text" | "ntext" => "text".to_string(),
+        "xml" => "xml".to_string(),
+        "binary" | "varbinary" | "image" | "timestamp" | "rowversion" => "bytea".to_string(),
+        "time" => format!("time({})", fsp),
+        "smalldatetime" => "timestamp(0)".to_string(),
+        "datetime" => "timestamp(3)".to_string(),
+        "datetime2" | "datetimeoffset" => format!("timestamp({})", fsp),
+        "uniqueidentifier" => "uuid".to_string(),
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn mysql_to_postgres(column: &ColumnDetail) -> Option<String> {
+    let len = column.max_length.unwrap_or(0);
+    let fsp = column.scale.unwrap_or(0);
+    let unsigned = column.column_type.contains("unsigned");
+    let t = match (column.data_type.as_str(), unsigned) {
+        ("tinyint", _) if column.column_type.starts_with("tinyint(1)") => "boolean".to_string(),
+        ("tinyint", _) | ("smallint", false) => "smallint".to_string(),
+        ("smallint", true) | ("mediumint", _) | ("int", false) => "integer".to_string(),
+        ("int", true) | ("bigint", false) => "bigint".to_string(),
+        ("bigint", true) => "numeric(20,0)".to_string(),
+        ("float", _) => "real".to_string(),
+        ("double", _) => "double precision".to_string(),
+        ("decimal", _) => match (column.precision, column.scale) {
+            (Some(p), Some(s)) => format!("numeric({},{})", p, s),
+            _ => "numeric".to_string(),
+        },
+        ("char", _) if len > 0 => format!("char({})", len),
+        ("varchar" | "enum" | "set", _) if len > 0 => format!("varchar({})", len),
+        ("tinytext" | "text" | "mediumtext" | "longtext", _) => "text".to_string(),
+        ("json", _) => "jsonb".to_string(),
+        ("binary" | "varbinary" | "tinyblob" | "blob" | "mediumblob" | "longblob", _) => {
+            "bytea".to_string()
+        }
+        ("date", _) => "date".to_string(),
+        ("datetime", _) => format!("timestamp({})", fsp),
+        ("timestamp", _) => format!("timestamptz({})", fsp),
+        ("time", _) => format!("time({})", fsp),
+        ("year", _) => "smallint".to_string(),
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn postgres_to_mysql(column: &ColumnDetail) -> Option<String> {
+    let len = column.max_length.unwrap_or(0);
+    let fsp = column.scale.unwrap_or(0).min(6);
+    let t = match column.data_type.as_str() {
+        "boolean" => "tinyint(1)".to_string(),
+        "smallint" => "smallint".to_string(),
+        "integer" => "int".to_string(),
+        "bigint" => "bigint".to_string(),
+        "real" => "float".to_string(),
+        "double precision" => "double".to_string(),
+        "numeric" => match (column.precision, column.scale) {
+            (Some(p), Some(s)) if p <= 65 => format!("decimal({},{})", p, s),
+            _ => return None,
+        },
+        "character" if (1..=255).contains(&len) => format!("char({})", len),
+        "character varying" if (1..=16383).contains(&len) => format!("varchar({})", len),
+        "character" | "character varying" | "text" | "xml" => "longtext".to_string(),
+        "json" | "jsonb" => "json".to_string(),
+        "bytea" => "longblob".to_string(),
+        "date" => "date".to_string(),
+        "time without time zone" => format!("time({})", fsp),
+        "timestamp without time zone" | "timestamp with time zone" => {
+            format!("datetime({})", fsp)
+        }
+        "uuid" => "char(36)".to_string(),
+        _ => return None,
+    };
+    Some(t)
+}
+
+fn postgres_to_mssql(column: &ColumnDetail) -> Option<String> {
+    let len = column.max_length.unwrap_or(0);
+    let fsp = column.scale.unwrap_or(0).min(7);
+    let t = match column.data_type.as_str() {
+        "boolean" => "bit".to_string(),
+        "smallint" => "smallint".to_string(),
+        "integer" => "int".to_string(),
+        "bigint" => "bigint".to_string(),
+        "real" => "real".to_string(),
+        "double precision" => "float".to_string(),
+        "numeric" => match (column.precision, column.scale) {
+            (Some(p), Some(s)) if p <= 38 => format!("decimal({},{})", p, s),
+            _ => return None,
+        },
+        "character" if (1..=4000).contains(&len) => format!("nchar({})", len),
+        "character varying" if (1..=4000).contains(&len) => format!("nvarchar({})", len),
+        "character" | "character varying" | "text" | "json" | "jsonb" => {
+            "nvarchar(max)".to_string()
+        }
+        "xml" => "xml".to_string(),
+        "bytea" => "varbinary(max)".to_string(),
+        "date" => "date".to_string(),
+        "time without time zone" => format!("time({})", fsp),
+        "timestamp without time zone" => format!("datetime2({})", fsp),
+        "timestamp with time zone" => format!("datetimeoffset({})", fsp),
+        "uuid" => "uniqueidentifier".to_string(),
+        _ => return None,
+    };
+    Some(t)
+}
+
+/// MySQL column type without display widths, which sqlite cannot parse in
+/// front of `unsigned`.
+fn mysql_to_sqlite(column: &ColumnDetail) -> String {
+    let t = column.data_type.as_str();
+    let unsigned = if column.column_type.contains("unsigned") {
+        " unsigned"
+    } else {
+        ""
+    };
+    match (t, column.max_length, column.precision, column.scale) {
+        ("tinyint", _, _, _) if column.column_type.starts_with("tinyint(1)") => {
+            "tinyint(1)".to_string()
+        }
+        ("char" | "varchar" | "binary" | "varbinary", Some(len), _, _) => {
+            format!("{}({})", t, len)
+        }
+        ("enum" | "set", Some(len), _, _) => format!("varchar({})", len),
+        ("decimal", _, Some(p), Some(s)) => format!("decimal({},{}){}", p, s, unsigned),
+        ("datetime" | "timestamp" | "time", _, _, Some(fsp)) if fsp > 0 => {
+            format!("{}({})", t, fsp)
+        }
+        _ => format!("{}{}", t, unsigned),
+    }
+}
+
+/// Reads a sqlite column as the MySQL type it stands for. Columns created by
+/// this tool already use MySQL names; the rest are mapped by their affinity.
+fn sqlite_as_mysql(column: &ColumnDetail) -> Option<ColumnDetail> {
+    let t = match (column.data_type.as_str(), column.max_length) {
+        ("integer", _) => "bigint".to_string(),
+        ("real" | "double precision" | "float", _) => "double".to_string(),
+        ("numeric", _) => match (column.precision, column.scale) {
+            (Some(p), Some(s)) => format!("decimal({},{})", p, s),
+            _ => return None,
+        },
+        ("boolean" | "bool", _) => "tinyint(1)".to_string(),
+        ("nchar" | "character", Some(len)) => format!("char({})", len),
+        ("nvarchar" | "varying character", Some(len)) => format!("varchar({})", len),
+        ("text" | "clob" | "nchar" | "nvarchar" | "character" | "varying character", _) => {
+            "longtext".to_string()
+        }
+        ("" | "blob", _) => "longblob".to_string(),
+        _ => return Some(column.clone()),
+    };
+    let data_type = t.split('(').next().unwrap_or_default().to_string();
+    Some(ColumnDetail {
+        data_type,
+        column_type: t,
+        ..column.clone()
+    })
 }
 
-impl Default for DatabaseConfig {
+/// Rows read and written per batch. The default and per-table sizes can be
+/// set in `conf.ini`:
+///
+/// ```ini
+/// [BATCHSIZE]
+/// default=1000
+/// Orders=5000
+/// ```
+#[derive(Debug, Clone)]
+pub struct BatchSize {
+    default: usize,
+    tables: HashMap<String, usize>,
+}
+
+impl Default for BatchSize {
     fn default() -> Self {
         Self {
-            class: Default::default(),
-            host: Default::default(),
-            port: Default::default(),
-            user: Default::default(),
-            password: Default::default(),
-            default_db: Default::default(),
+            default: 1000,
+            tables: HashMap::new(),
         }
     }
 }
 
-impl DatabaseConfig {
-    pub fn from_config(direct: Direct) -> Self {
-        let conf = Ini::load_from_file("conf.ini").unwrap();
-        let inf = conf.section(Some(direct.to_str())).unwrap();
-        Self {
-            class: inf.get("databaseType").unwrap().to_string(),
-            host: inf.get("host").unwrap().to_string(),
-            port: inf.get("port").unwrap().to_string(),
-            user: inf.get("user").unwrap().to_string(),
-            password: inf.get("password").unwrap().to_string(),
-            default_db: inf.get("db").unwrap().to_string(),
+impl BatchSize {
+    pub fn from_config() -> Result<Self> {
+        let mut size = Self::default();
+        if let Ok(conf) = Ini::load_from_file("conf.ini") {
+            if let Some(inf) = conf.section(Some("BATCHSIZE")) {
+                for (k, v) in inf.iter() {
+                    let v = positive("BATCHSIZE", k, v)?;
+                    match k.trim().to_lowercase().as_str() {
+                        "default" => size.default = v,
+                        table => {
+                            size.tables.insert(table.to_string(), v);
+                        }
+                    }
+                }
+            }
         }
+        Ok(size)
+    }
+
+    /// Size for `table`, looked up by its full and its bare name.
+    pub fn get(&self, table: &str) -> usize {
+        let table = table.to_lowercase();
+        let name = table.rsplit('.').next().unwrap_or_default();
+        self.tables
+            .get(&table)
+            .or_else(|| self.tables.get(name))
+            .copied()
+            .unwrap_or(self.default)
     }
 }
-#[derive(Debug, Clone, Default)]
-pub struct DatabaseMeta {
-    from_db: DatabaseConfig,
-    to_db: DatabaseConfig,
+
+/// Retries of transient errors when connecting and writing a batch, waiting
+/// `delay` milliseconds before the first and twice as long before each next
+/// one, up to `max_delay`:
+///
+/// ```ini
+/// [RETRY]
+/// retries=3
+/// delay=1000
+/// max_delay=30000
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    retries: u32,
+    delay: Duration,
+    max_delay: Duration,
 }
 
-impl DatabaseMeta {
-    pub fn initial() -> Self {
+impl Default for Retry {
+    fn default() -> Self {
         Self {
-            from_db: DatabaseConfig::from_config(Direct::FROM),
-            to_db: DatabaseConfig::from_config(Direct::TO),
+            retries: 3,
+            delay: Duration::from_millis(1000),
+            max_delay: Duration::from_millis(30000),
         }
     }
+}
 
-    pub async fn client(self, direct: Direct) -> Result<SQLClient, Box<dyn std::error::Error>> {
-        let db = match direct {
-            Direct::FROM => self.from_db,
-            Direct::TO => self.to_db,
+impl Retry {
+    pub fn from_config() -> Result<Self> {
+        let mut retry = Self::default();
+        if let Ok(conf) = Ini::load_from_file("conf.ini") {
+            if let Some(inf) = conf.section(Some("RETRY")) {
+                let get = |key: &str| {
+                    inf.get(key)
+                        .map(|x| {
+                            x.trim().parse::<u32>().map_err(|_| {
+                                Error::config(
+                                    "RETRY",
+                                    key,
+                                    format!("`{}` is not a whole number", x.trim()),
+                                )
+                            })
+                        })
+                        .transpose()
+                };
+                if let Some(x) = get("retries")? {
+                    retry.retries = x;
+                }
+                if let Some(x) = get("delay")? {
+                    retry.delay = Duration::from_millis(x as u64);
+                }
+                if let Some(x) = get("max_delay")? {
+                    retry.max_delay = Duration::from_millis(x as u64);
+                }
+            }
+        }
+        Ok(retry)
+    }
+
+    /// How long to wait before retry number `attempt`, counting from 1, after
+    /// `e`, or `None` when `e` is permanent or the retries are used up.
+    fn backoff(&self, attempt: u32, e: &(dyn std::error::Error + 'static)) -> Option<Duration> {
+        if attempt > self.retries || !driver::classify(e).is_transient() {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt - 1);
+        Some(self.delay.saturating_mul(factor).min(self.max_delay))
+    }
+}
+
+/// How a copied table is checked against its source:
+///
+/// ```ini
+/// [VERIFY]
+/// mode=count
+/// ```
+///
+/// `count` compares row counts, `aggregates` also the non-null count and the
+/// smallest and largest value of every number and date column, `hash` also a
+/// hash over all rows.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum VerifyMode {
+    Off,
+    #[default]
+    Count,
+    Aggregates,
+    Hash,
+}
+
+impl VerifyMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "off" => Some(VerifyMode::Off),
+            "count" => Some(VerifyMode::Count),
+            "aggregates" => Some(VerifyMode::Aggregates),
+            "hash" => Some(VerifyMode::Hash),
+            _ => None,
+        }
+    }
+
+    pub fn from_config() -> Result<Self> {
+        let conf = match Ini::load_from_file("conf.ini") {
+            Ok(conf) => conf,
+            Err(_) => return Ok(Self::default()),
         };
+        match conf.section(Some("VERIFY")).and_then(|x| x.get("mode")) {
+            Some(mode) => Self::parse(mode).ok_or_else(|| {
+                Error::config(
+                    "VERIFY",
+                    "mode",
+                    format!(
+                        "unknown mode `{}`, expected off, count, aggregates or hash",
+                        mode
+                    ),
+                )
+            }),
+            None => Ok(Self::default()),
+        }
+    }
+}
 
-        match db.class.as_str() {
-            "MSSQL" => {
-                let mut config = Config::new();
-                config.host(db.host);
-                config.port(db.port.parse::<u16>().unwrap());
-                config.authentication(AuthMethod::sql_server(db.user, db.password));
-                config.trust_cert();
-
-                let tcp = TcpStream::connect(config.get_addr()).await?;
-                //tcp.set_nodelay(true)?;
-
-                let client = match Client::connect(config, tcp.compat_write()).await {
-                    // Connection successful.
-                    Ok(client) => client,
-                    // The server wants us to redirect to a different address
-                    Err(e) => Err(e)?,
-                };
+/// Outcome of copying one table.
+#[derive(Debug, Clone)]
+pub struct TableReport {
+    pub rows: u64,
+    /// differences found by `DatabaseMeta::verify_table`, `None` when not verified
+    pub mismatches: Option<Vec<String>>,
+    /// highest watermark copied so far, for incremental tables
+    pub watermark: Option<String>,
+    /// rows deleted or marked in the target, for tables under `[DELETES]`
+    pub deleted: Option<u64>,
+}
 
-                Ok(SQLClient::Mssql(client))
-            }
-            _ => {
-                let database_url = format!(
-                    "{class}://{user}:{password}@{host}:{port}/{db}",
-                    class = db.class,
-                    user = db.user,
-                    password = db.password,
-                    host = db.host,
-                    port = db.port,
-                    db = db.default_db
-                );
+impl TableReport {
+    /// `pass` or `FAIL` once verified.
+    pub fn badge(&self) -> Option<&'static str> {
+        self.mismatches
+            .as_ref()
+            .map(|x| if x.is_empty() { "pass" } else { "FAIL" })
+    }
+}
 
-                let opts = Opts::from_url(&database_url).expect("DATABASE_URL invalid");
-                let pool = Pool::new(opts);
-                let client = pool.get_conn().await?;
-                Ok(SQLClient::Mysql((client, pool)))
-            }
+/// How values of one column are compared between source and target, beyond
+/// `Value::canonical`: fractional seconds rounded to the digits both sides
+/// keep, such as MSSQL `datetime` in steps of 1/300 copied to `datetime(3)`,
+/// and the padding of fixed length text dropped, which MySQL `CHAR` strips.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Compare {
+    digits: Option<u16>,
+    trim: bool,
+    /// text columns keep values such as `007` as they are
+    textual: bool,
+}
+
+impl Compare {
+    /// For a column declared as `source` on one side and `target`, when it
+    /// exists, on the other.
+    pub(crate) fn new(source: &ColumnDetail, target: Option<&ColumnDetail>) -> Self {
+        let sides = std::iter::once(source)
+            .chain(target)
+            .collect::<Vec<&ColumnDetail>>();
+        let digits = sides
+            .iter()
+            .filter(|x| x.data_type.contains("time"))
+            .filter_map(|x| x.scale)
+            .min()
+            .map(|x| x.clamp(0, 9) as u16);
+        let trim = sides.iter().any(|x| {
+            matches!(
+                x.data_type.as_str(),
+                "char" | "nchar" | "character" | "bpchar"
+            )
+        });
+        let textual = sides.iter().all(|x| textual(&x.data_type));
+        Self {
+            digits,
+            trim,
+            textual,
         }
     }
 
-    pub fn get_default_db(self, direct: Direct) -> String {
-        let db = match direct {
-            Direct::FROM => self.from_db,
-            Direct::TO => self.to_db,
+    /// For columns whose declarations on the other side are in `target`.
+    pub(crate) fn columns(source: &[ColumnDetail], target: &[ColumnDetail]) -> Vec<Self> {
+        source
+            .iter()
+            .map(|column| {
+                let other = target
+                    .iter()
+                    .find(|x| x.name.eq_ignore_ascii_case(&column.name));
+                Self::new(column, other)
+            })
+            .collect()
+    }
+
+    /// `value` in the form compared. `None` for null.
+    pub(crate) fn text(self, value: &Value) -> Option<String> {
+        let text = match (value, self.digits) {
+            (Value::Text(x), _) if self.textual => x.clone(),
+            (_, Some(digits)) => value.round_seconds(digits).canonical()?,
+            (_, None) => value.canonical()?,
         };
-        db.default_db
+        Some(match self.trim {
+            true => text.trim_end_matches(' ').to_string(),
+            false => text,
+        })
+    }
+}
+
+/// Text types, whose values are compared and ordered as text even when they
+/// read as numbers.
+fn textual(data_type: &str) -> bool {
+    ["char", "text", "uniqueidentifier"]
+        .iter()
+        .any(|x| data_type.contains(x))
+}
+
+/// Types whose smallest and largest value read the same on every backend.
+fn comparable(data_type: &str) -> bool {
+    matches!(
+        data_type,
+        "tinyint"
+            | "smallint"
+            | "mediumint"
+            | "int"
+            | "integer"
+            | "bigint"
+            | "decimal"
+            | "numeric"
+            | "money"
+            | "smallmoney"
+            | "date"
+            | "datetime"
+            | "datetime2"
+            | "smalldatetime"
+            | "timestamp"
+            | "timestamp without time zone"
+    )
+}
+
+/// Caps how many tables are copied at the same time. Clones share the cap.
+fn workers_from_config() -> Result<usize> {
+    let workers = Ini::load_from_file("conf.ini").ok().and_then(|conf| {
+        conf.section(Some("TRANSFER"))?
+            .get("workers")
+            .map(String::from)
+    });
+    match workers {
+        Some(v) => positive("TRANSFER", "workers", &v),
+        None => Ok(4),
+    }
+}
+
+/// A count under `[section]`, which must be a whole number above zero.
+fn positive(section: &str, key: &str, v: &str) -> Result<usize> {
+    match v.trim().parse::<usize>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(Error::config(
+            section,
+            key.trim(),
+            format!("`{}` is not a positive number", v.trim()),
+        )),
     }
+}
+
+/// Tables read as several key ranges over connections of their own. The
+/// key defaults to the first primary key column and must hold integers or
+/// dates:
+///
+/// ```ini
+/// [CHUNKS]
+/// Orders=8
+/// Orders.key=OrderDate
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Chunks {
+    default: usize,
+    key: Option<String>,
+    tables: HashMap<String, usize>,
+    keys: HashMap<String, String>,
+}
 
-    pub async fn show_dbs(self, direct: Direct) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let client = self.client(direct).await?;
-        let res = match client {
-            SQLClient::Mysql((mut c, p)) => {
-                let mut result = c.query_iter("show databases").await?;
-                let res = result.collect::<String>().await?;
-                drop(c);
-                p.disconnect().await?;
-                res
+impl Chunks {
+    pub fn from_config() -> Result<Self> {
+        let mut chunks = Self::default();
+        if let Ok(conf) = Ini::load_from_file("conf.ini") {
+            if let Some(inf) = conf.section(Some("CHUNKS")) {
+                for (k, v) in inf.iter() {
+                    let k = k.trim().to_lowercase();
+                    match k.strip_suffix(".key") {
+                        Some(table) => {
+                            chunks.keys.insert(table.to_string(), v.trim().to_string());
+                        }
+                        None => {
+                            let v = positive("CHUNKS", &k, v)?;
+                            chunks.tables.insert(k, v);
+                        }
+                    }
+                }
             }
-            SQLClient::Mssql(mut c) => {
-                let row = c
-                    .simple_query(" SELECT  name FROM SysDatabases")
-                    .await?
-                    .into_results()
-                    .await?;
-                drop(c);
-                let res = row[0]
-                    .iter()
-                    .map(|x| {
-                        let r: &str = x.get(0).unwrap();
-                        r.to_string()
-                    })
-                    .collect::<Vec<String>>();
+        }
+        Ok(chunks)
+    }
+
+    /// Chunk count and key column for `table`, looked up by its full and its
+    /// bare name.
+    pub fn get(&self, table: &str) -> (usize, Option<String>) {
+        let table = table.to_lowercase();
+        let name = table.rsplit('.').next().unwrap_or_default();
+        let count = self
+            .tables
+            .get(&table)
+            .or_else(|| self.tables.get(name))
+            .copied()
+            .unwrap_or(self.default);
+        let key = self
+            .keys
+            .get(&table)
+            .or_else(|| self.keys.get(name))
+            .or(self.key.as_ref())
+            .cloned();
+        (count, key)
+    }
+}
+
+/// Tables copied incrementally, each run reading only the rows whose
+/// watermark column grew past the highest value copied before:
+///
+/// ```ini
+/// [INCREMENTAL]
+/// Orders=UpdatedAt
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Incremental {
+    default: Option<String>,
+    tables: HashMap<String, String>,
+}
 
-                res
+impl Incremental {
+    pub fn from_config() -> Self {
+        let mut incremental = Self::default();
+        if let Ok(conf) = Ini::load_from_file("conf.ini") {
+            if let Some(inf) = conf.section(Some("INCREMENTAL")) {
+                for (k, v) in inf.iter() {
+                    incremental
+                        .tables
+                        .insert(k.trim().to_lowercase(), v.trim().to_string());
+                }
             }
+        }
+        incremental
+    }
+
+    /// Watermark column of `table`, looked up by its full and its bare name.
+    pub fn get(&self, table: &str) -> Option<String> {
+        let table = table.to_lowercase();
+        let name = table.rsplit('.').next().unwrap_or_default();
+        self.tables
+            .get(&table)
+            .or_else(|| self.tables.get(name))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+/// What a copy does with the rows already in the target table:
+///
+/// ```ini
+/// [WRITEMODE]
+/// default=append
+/// Orders=upsert
+/// Orders.key=OrderID
+/// ```
+///
+/// `append` inserts and fails on a duplicate key, `truncate` empties the
+/// table first, `upsert` overwrites rows with the same key and
+/// `insert-ignore` keeps them. The key defaults to the primary key of the
+/// target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    #[default]
+    Append,
+    Truncate,
+    Upsert,
+    InsertIgnore,
+}
+
+impl WriteMode {
+    pub const ALL: [WriteMode; 4] = [
+        WriteMode::Append,
+        WriteMode::Truncate,
+        WriteMode::Upsert,
+        WriteMode::InsertIgnore,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WriteMode::Append => "append",
+            WriteMode::Truncate => "truncate",
+            WriteMode::Upsert => "upsert",
+            WriteMode::InsertIgnore => "insert-ignore",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "append" | "insert" => Some(WriteMode::Append),
+            "truncate" | "truncate-then-insert" => Some(WriteMode::Truncate),
+            "upsert" | "merge" => Some(WriteMode::Upsert),
+            "insert-ignore" | "ignore" => Some(WriteMode::InsertIgnore),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WriteMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Write modes and key columns by table, from `[WRITEMODE]`.
+#[derive(Debug, Clone, Default)]
+pub struct WriteModes {
+    default: WriteMode,
+    tables: HashMap<String, WriteMode>,
+    keys: HashMap<String, Vec<String>>,
+}
+
+impl WriteModes {
+    pub fn from_config() -> Result<Self> {
+        let mut modes = Self::default();
+        let conf = match Ini::load_from_file("conf.ini") {
+            Ok(conf) => conf,
+            Err(_) => return Ok(modes),
         };
-        Ok(res)
+        if let Some(inf) = conf.section(Some("WRITEMODE")) {
+            for (k, v) in inf.iter() {
+                let k = k.trim().to_lowercase();
+                if let Some(table) = k.strip_suffix(".key") {
+                    let key = v
+                        .split(',')
+                        .map(|x| x.trim().to_string())
+                        .filter(|x| !x.is_empty())
+                        .collect::<Vec<String>>();
+                    modes.keys.insert(table.to_string(), key);
+                    continue;
+                }
+                let mode = WriteMode::parse(v).ok_or_else(|| {
+                    Error::config(
+                        "WRITEMODE",
+                        &k,
+                        format!(
+                            "unknown mode `{}`, expected append, truncate, upsert or insert-ignore",
+                            v
+                        ),
+                    )
+                })?;
+                match k.as_str() {
+                    "default" => modes.default = mode,
+                    _ => {
+                        modes.tables.insert(k, mode);
+                    }
+                }
+            }
+        }
+        Ok(modes)
     }
 
-    pub async fn show_tables(
-        self,
-        direct: Direct,
-        ddb: String,
-    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let client = self.client(direct).await?;
-        let res = match client {
-            SQLClient::Mysql((mut c, p)) => {
-                let sql_str = format!(
-                    "select table_name from information_schema.tables where table_schema='{}' and table_type='base table';"
-                    ,ddb
-                );
-                let mut result = c.query_iter(sql_str).await?;
-                let res = result.collect::<String>().await?;
-                drop(c);
-                p.disconnect().await?;
-                res
-            }
-            SQLClient::Mssql(mut c) => {
-                let row = c
-                    .simple_query(" SELECT  name FROM SysDatabases")
-                    .await?
-                    .into_results()
-                    .await?;
+    /// Write mode and key columns of `table`, looked up by its full and its
+    /// bare name.
+    pub fn get(&self, table: &str) -> (WriteMode, Option<Vec<String>>) {
+        let table = table.to_lowercase();
+        let name = table.rsplit('.').next().unwrap_or_default();
+        let mode = self
+            .tables
+            .get(&table)
+            .or_else(|| self.tables.get(name))
+            .copied()
+            .unwrap_or(self.default);
+        let key = self
+            .keys
+            .get(&table)
+            .or_else(|| self.keys.get(name))
+            .cloned();
+        (mode, key)
+    }
+}
 
-                let res = row[0]
-                    .iter()
-                    .map(|x| {
-                        let r: &str = x.get(0).unwrap();
-                        r.to_string()
-                    })
-                    .collect::<Vec<String>>();
+/// How rows deleted at the source are found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeleteCheck {
+    /// keys the target holds and the source no longer does
+    Keys,
+    /// rows whose flag column is set, e.g. `flag:IsDeleted`
+    Flag(String),
+}
 
-                res
+impl DeleteCheck {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        match s.split_once(':') {
+            Some((kind, column)) if kind.trim().eq_ignore_ascii_case("flag") => {
+                match column.trim() {
+                    "" => None,
+                    column => Some(DeleteCheck::Flag(column.to_string())),
+                }
             }
+            None if s.eq_ignore_ascii_case("keys") => Some(DeleteCheck::Keys),
+            _ => None,
+        }
+    }
+}
+
+/// Tables whose deleted rows are removed from the target, from `[DELETES]`,
+/// and the columns marking them instead.
+#[derive(Debug, Clone, Default)]
+pub struct Deletes {
+    default: Option<DeleteCheck>,
+    tables: HashMap<String, DeleteCheck>,
+    marks: HashMap<String, String>,
+}
+
+impl Deletes {
+    pub fn from_config() -> Result<Self> {
+        let mut deletes = Self::default();
+        let conf = match Ini::load_from_file("conf.ini") {
+            Ok(conf) => conf,
+            Err(_) => return Ok(deletes),
         };
-        Ok(res)
+        if let Some(inf) = conf.section(Some("DELETES")) {
+            for (k, v) in inf.iter() {
+                let k = k.trim().to_lowercase();
+                if let Some(table) = k.strip_suffix(".mark") {
+                    if !v.trim().is_empty() {
+                        deletes
+                            .marks
+                            .insert(table.to_string(), v.trim().to_string());
+                    }
+                    continue;
+                }
+                let check = DeleteCheck::parse(v).ok_or_else(|| {
+                    Error::config(
+                        "DELETES",
+                        &k,
+                        format!("unknown check `{}`, expected keys or flag:COLUMN", v),
+                    )
+                })?;
+                match k.as_str() {
+                    "default" => deletes.default = Some(check),
+                    _ => {
+                        deletes.tables.insert(k, check);
+                    }
+                }
+            }
+        }
+        Ok(deletes)
     }
 
-    pub async fn table_detail(
-        self,
-        direct: Direct,
-        ddb: String,
-        table: String,
-    ) -> Result<Vec<(String, String, String, String)>, Box<dyn std::error::Error>> {
-        let client = self.client(direct).await?;
-        let res = match client {
-            SQLClient::Mysql((mut c, p)) => {
-                let sql_str = format!(
-                    "SELECT
-                    COLUMN_NAME,
-                    
-                    DATA_TYPE,
-                    CHARACTER_MAXIMUM_LENGTH,
-                    COLUMN_COMMENT
-                FROM
-                    information_schema.`COLUMNS` 
-                WHERE
-                    TABLE_SCHEMA = '{}' 
-                    AND table_name = '{}' 
-                ORDER BY
-                    TABLE_NAME,
-                    ORDINAL_POSITION;",
-                    ddb, table
-                );
-                println!("{}", sql_str);
-                let mut result = c.query_iter(sql_str).await?;
-                let res = result.collect::<(String, String, String, String)>().await?;
-                drop(c);
-
-                p.disconnect().await?;
-                res
-            }
-            SQLClient::Mssql(mut c) => {
-                let sql_str = format!(
-                    "SELECT
-                a.name AS COLUMN_NAME,
-                --isnull( e.text, '' ) AS COLUMN_DEFAULT,
-                b.name AS DATA_TYPE,
-                COLUMNPROPERTY( a.id, a.name, 'PRECISION' ) AS CHARACTER_MAXIMUM_LENGTH,
-                isnull( g.[value], '' ) AS COLUMN_COMMENT 
-            FROM
-                syscolumns a
-                LEFT JOIN systypes b ON a.xtype= b.xusertype
-                INNER JOIN sysobjects d ON a.id= d.id 
-                AND d.xtype= 'U' 
-                AND d.name<> 'dtproperties'
-                LEFT JOIN syscomments e ON a.cdefault= e.id
-                LEFT JOIN sys.extended_properties g ON a.id= g.major_id 
-                AND a.colid= g.minor_id
-                LEFT JOIN sys.extended_properties f ON d.id= f.major_id 
-                AND f.minor_id = 0 
-            WHERE
-                d.name= '{}' 
-            ORDER BY
-                a.id,
-                a.colorder",
-                    table
-                );
-                println!("{}", sql_str);
-                let row = c.simple_query(sql_str).await?.into_results().await?;
+    /// How deletions in `table` are found and the column marking them, looked
+    /// up by its full and its bare name. `None` when they are not looked for.
+    pub fn get(&self, table: &str) -> Option<(DeleteCheck, Option<String>)> {
+        let table = table.to_lowercase();
+        let name = table.rsplit('.').next().unwrap_or_default();
+        let check = self
+            .tables
+            .get(&table)
+            .or_else(|| self.tables.get(name))
+            .or(self.default.as_ref())?
+            .clone();
+        let mark = self
+            .marks
+            .get(&table)
+            .or_else(|| self.marks.get(name))
+            .or_else(|| self.marks.get("default"))
+            .cloned();
+        Some((check, mark))
+    }
+}
 
-                let res = row[0]
-                    .iter()
-                    .map(|x| {
-                        let r1: &str = x.get(0).unwrap();
-                        let r2: &str = x.get(0).unwrap();
-                        let r3: &str = x.get(0).unwrap();
-                        let r4: &str = x.get(0).unwrap();
-                        (
-                            r1.to_string(),
-                            r2.to_string(),
-                            r3.to_string(),
-                            r4.to_string(),
-                        )
-                    })
-                    .collect::<Vec<(String, String, String, String)>>();
+/// How a flag column reads as set and the value setting it: true or 1 for
+/// boolean and integer columns, and any value for others, which are set to
+/// the current time.
+fn flag(dialect: Dialect, column: &ColumnDetail) -> (String, String) {
+    let name = dialect.quote(&column.name);
+    let on = match column.data_type.as_str() {
+        "boolean" | "bool" if dialect == Dialect::Postgres => "true",
+        "boolean" | "bool" | "bit" | "tinyint" | "smallint" | "mediumint" | "int" | "integer"
+        | "bigint" | "decimal" | "numeric" => "1",
+        _ => {
+            return (
+                format!("{} IS NOT NULL", name),
+                "CURRENT_TIMESTAMP".to_string(),
+            )
+        }
+    };
+    (format!("{} = {}", name, on), on.to_string())
+}
+
+/// Rows of an incremental copy: past the last watermark and up to the
+/// highest value when the copy started, so rows added meanwhile wait for the
+/// next run. Rows whose watermark is null are only read by the first run.
+struct Window {
+    column: String,
+    low: Option<Mark>,
+    high: Mark,
+    /// rows at the last watermark are read again, since rows committed after
+    /// the last run may share it; only when the target overwrites or skips
+    /// rows it already has
+    inclusive: bool,
+}
 
-                res
+impl Window {
+    fn condition(&self, dialect: Dialect) -> String {
+        let column = dialect.quote(&self.column);
+        match &self.low {
+            Some(low) => format!(
+                "({} {} {} AND {} <= {})",
+                column,
+                if self.inclusive { ">=" } else { ">" },
+                low.literal(dialect),
+                column,
+                self.high.literal(dialect)
+            ),
+            None => format!(
+                "({} IS NULL OR {} <= {})",
+                column,
+                column,
+                self.high.literal(dialect)
+            ),
+        }
+    }
+}
+
+/// What a chunk key holds, with values counted in days for dates and
+/// seconds for datetimes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum KeyKind {
+    #[default]
+    Int,
+    Date,
+    DateTime,
+}
+
+impl KeyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyKind::Int => "int",
+            KeyKind::Date => "date",
+            KeyKind::DateTime => "datetime",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "int" => Some(KeyKind::Int),
+            "date" => Some(KeyKind::Date),
+            "datetime" => Some(KeyKind::DateTime),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn key_point(value: &Value) -> Option<(KeyKind, i64)> {
+    match value {
+        Value::Int(x) => Some((KeyKind::Int, *x)),
+        Value::UInt(x) => Some((KeyKind::Int, i64::try_from(*x).ok()?)),
+        Value::Date(x) => Some((KeyKind::Date, x.num_days_from_ce() as i64)),
+        Value::DateTime(x) => Some((KeyKind::DateTime, x.and_utc().timestamp())),
+        // sqlite keeps dates as text, other backends may hand numbers back as text
+        Value::Text(x) | Value::Decimal(x) => {
+            if let Ok(x) = x.parse::<i64>() {
+                Some((KeyKind::Int, x))
+            } else if let Ok(x) = NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f") {
+                Some((KeyKind::DateTime, x.and_utc().timestamp()))
+            } else if let Ok(x) = NaiveDate::parse_from_str(x, "%Y-%m-%d") {
+                Some((KeyKind::Date, x.num_days_from_ce() as i64))
+            } else {
+                None
             }
-        };
-        Ok(res)
+        }
+        _ => None,
+    }
+}
+
+fn key_literal(dialect: Dialect, kind: KeyKind, point: i64) -> String {
+    match kind {
+        KeyKind::Int => point.to_string(),
+        KeyKind::Date => NaiveDate::from_num_days_from_ce_opt(point as i32)
+            .map(|x| dialect.literal(&x.format("%Y-%m-%d").to_string()))
+            .unwrap_or_default(),
+        KeyKind::DateTime => {
+            let format = match dialect {
+                Dialect::Mssql => "%Y-%m-%dT%H:%M:%S",
+                _ => "%Y-%m-%d %H:%M:%S",
+            };
+            DateTime::from_timestamp(point, 0)
+                .map(|x| x.naive_utc())
+                .map(|x| dialect.literal(&x.format(format).to_string()))
+                .unwrap_or_default()
+        }
+    }
+}
+
+/// The rows of one chunk: those whose key is null, or whose key lies in
+/// `[low, high)` with `None` leaving that end open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyRange {
+    Null,
+    Between(Option<i64>, Option<i64>),
+}
+
+impl KeyRange {
+    /// Condition selecting the range in `dialect`, starting at `from` instead
+    /// of its low end when given.
+    pub fn condition(
+        self,
+        dialect: Dialect,
+        key: &str,
+        kind: KeyKind,
+        from: Option<i64>,
+    ) -> Option<String> {
+        let key = dialect.quote(key);
+        let (low, high) = match self {
+            KeyRange::Null => return Some(format!("{} IS NULL", key)),
+            KeyRange::Between(low, high) => (from.or(low), high),
+        };
+        let mut parts = vec![];
+        if let Some(low) = low {
+            parts.push(format!("{} >= {}", key, key_literal(dialect, kind, low)));
+        }
+        if let Some(high) = high {
+            parts.push(format!("{} < {}", key, key_literal(dialect, kind, high)));
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" AND "))
+        }
+    }
+
+    /// `null`, or the bounds as `low..high` with open ends left empty.
+    pub fn to_text(self) -> String {
+        let bound = |x: Option<i64>| x.map(|x| x.to_string()).unwrap_or_default();
+        match self {
+            KeyRange::Null => "null".to_string(),
+            KeyRange::Between(low, high) => format!("{}..{}", bound(low), bound(high)),
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        if s == "null" {
+            return Some(KeyRange::Null);
+        }
+        let (low, high) = s.split_once("..")?;
+        let bound = |x: &str| match x {
+            "" => Ok(None),
+            x => x.parse::<i64>().map(Some),
+        };
+        Some(KeyRange::Between(bound(low).ok()?, bound(high).ok()?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerPool(Arc<Semaphore>);
+
+impl WorkerPool {
+    pub fn new(workers: usize) -> Self {
+        Self(Arc::new(Semaphore::new(workers.max(1))))
+    }
+
+    /// `DatabaseMeta::transfer_table`, `propagate_deletes` and `verify_table`
+    /// once a worker is free.
+    pub async fn transfer_table(
+        self,
+        db_meta: DatabaseMeta,
+        direct: Direct,
+        from_ddb: String,
+        to_ddb: String,
+        table: String,
+    ) -> Result<TableReport> {
+        let _permit = self.0.acquire().await?;
+        let rows = db_meta
+            .clone()
+            .transfer_table(direct, from_ddb.clone(), to_ddb.clone(), table.clone())
+            .await?;
+        let watermark = db_meta
+            .watermark(direct, &from_ddb, &to_ddb, &table)
+            .map(|x| x.text);
+        let deleted = db_meta
+            .clone()
+            .propagate_deletes(direct, from_ddb.clone(), to_ddb.clone(), table.clone())
+            .await?;
+        let mismatches = db_meta
+            .verify_table(direct, from_ddb, to_ddb, table)
+            .await?;
+        Ok(TableReport {
+            rows,
+            mismatches,
+            watermark,
+            deleted,
+        })
+    }
+}
+
+/// Runs `tasks` at the same time and returns their results in order. The
+/// first failure aborts the tasks still running, so none keeps writing
+/// once the caller has given up.
+pub(crate) async fn join_all<T, F>(tasks: Vec<F>) -> Result<Vec<T>>
+where
+    T: Send + 'static,
+    F: std::future::Future<Output = Result<T>> + Send + 'static,
+{
+    let mut set = JoinSet::new();
+    let mut results = Vec::with_capacity(tasks.len());
+    for (idx, task) in tasks.into_iter().enumerate() {
+        set.spawn(async move { (idx, task.await) });
+        results.push(None);
+    }
+    // dropping the set on an early return aborts the rest
+    while let Some(joined) = set.join_next().await {
+        let (idx, res) = joined?;
+        results[idx] = Some(res?);
+    }
+    Ok(results.into_iter().flatten().collect())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseMeta {
+    from_db: DatabaseConfig,
+    to_db: DatabaseConfig,
+    type_map: TypeMap,
+    batch_size: BatchSize,
+    chunks: Chunks,
+    workers: usize,
+    retry: Retry,
+    checkpoints: Checkpoints,
+    /// continue unfinished transfers from `checkpoint.ini`
+    resume: bool,
+    verify: VerifyMode,
+    incremental: Incremental,
+    watermarks: Watermarks,
+    write_modes: WriteModes,
+    deletes: Deletes,
+}
+
+impl DatabaseMeta {
+    pub fn initial() -> Result<Self> {
+        Ok(Self {
+            from_db: DatabaseConfig::from_config(Direct::FROM)?,
+            to_db: DatabaseConfig::from_config(Direct::TO)?,
+            type_map: TypeMap::from_config(),
+            batch_size: BatchSize::from_config()?,
+            chunks: Chunks::from_config()?,
+            workers: workers_from_config()?,
+            retry: Retry::from_config()?,
+            checkpoints: Checkpoints::load(),
+            resume: false,
+            verify: VerifyMode::from_config()?,
+            incremental: Incremental::from_config(),
+            watermarks: Watermarks::load(),
+            write_modes: WriteModes::from_config()?,
+            deletes: Deletes::from_config()?,
+        })
+    }
+
+    /// Tables copied at the same time, `workers` under `[TRANSFER]`.
+    pub fn workers(&self) -> usize {
+        self.workers.max(1)
+    }
+
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers;
+    }
+
+    /// Writes MySQL targets with `LOAD DATA LOCAL INFILE`, falling back to
+    /// inserts when the server does not allow it.
+    pub fn set_load_data(&mut self, on: bool) {
+        self.from_db.load_data = on;
+        self.to_db.load_data = on;
+    }
+
+    /// Uses `size` for every table, overriding `conf.ini`.
+    pub fn set_batch_size(&mut self, size: usize) {
+        self.batch_size = BatchSize {
+            default: size,
+            tables: HashMap::new(),
+        };
+    }
+
+    /// Splits every table into `chunks` key ranges, by `key` or else by the
+    /// primary key, overriding `conf.ini`.
+    pub fn set_chunks(&mut self, chunks: usize, key: Option<String>) {
+        self.chunks = Chunks {
+            default: chunks,
+            key,
+            ..Default::default()
+        };
+    }
+
+    /// How long to wait before retry number `attempt` after `e`, following
+    /// `[RETRY]`, or `None` when `e` is not worth retrying.
+    pub(crate) fn backoff(&self, attempt: u32, e: &Error) -> Option<Duration> {
+        self.retry.backoff(attempt, e)
+    }
+
+    /// Rows read and written at a time for `table`.
+    pub fn batch_size(&self, table: &str) -> usize {
+        self.batch_size.get(table)
+    }
+
+    pub fn set_resume(&mut self, on: bool) {
+        self.resume = on;
+    }
+
+    pub fn set_verify(&mut self, mode: VerifyMode) {
+        self.verify = mode;
+    }
+
+    /// Copies every table incrementally by `column`, overriding `conf.ini`.
+    pub fn set_incremental(&mut self, column: String) {
+        self.incremental = Incremental {
+            default: Some(column),
+            ..Default::default()
+        };
+    }
+
+    /// Writes every table in `mode`, overriding the modes in `conf.ini` but
+    /// not the key columns named there.
+    pub fn set_write_mode(&mut self, mode: WriteMode) {
+        self.write_modes.default = mode;
+        self.write_modes.tables.clear();
+    }
+
+    /// Looks for deletions in every table by `check`, overriding `[DELETES]`
+    /// but not the mark columns named there.
+    pub fn set_deletes(&mut self, check: DeleteCheck) {
+        self.deletes.default = Some(check);
+        self.deletes.tables.clear();
+    }
+
+    pub(crate) fn checkpoint_id(
+        &self,
+        direct: Direct,
+        from_ddb: &str,
+        to_ddb: &str,
+        table: &str,
+    ) -> String {
+        let (from, to) = match direct {
+            Direct::FROM => (&self.from_db, &self.to_db),
+            Direct::TO => (&self.to_db, &self.from_db),
+        };
+        format!(
+            "{} {}.{} > {} {}",
+            from.label(),
+            from_ddb,
+            table,
+            to.label(),
+            to_ddb
+        )
+    }
+
+    /// Whether copying `table` was interrupted and can be resumed.
+    pub fn has_checkpoint(
+        &self,
+        direct: Direct,
+        from_ddb: &str,
+        to_ddb: &str,
+        table: &str,
+    ) -> bool {
+        self.checkpoints
+            .get(&self.checkpoint_id(direct, from_ddb, to_ddb, table))
+            .is_some()
+    }
+
+    /// Highest watermark copied of `table`, when it is copied incrementally.
+    pub fn watermark(
+        &self,
+        direct: Direct,
+        from_ddb: &str,
+        to_ddb: &str,
+        table: &str,
+    ) -> Option<Mark> {
+        self.watermarks
+            .get(&self.checkpoint_id(direct, from_ddb, to_ddb, table))?
+            .value
+    }
+
+    /// Connects to the `direct` side, retrying transient failures.
+    pub async fn client(self, direct: Direct) -> Result<Box<dyn Driver>> {
+        let db = match direct {
+            Direct::FROM => self.from_db,
+            Direct::TO => self.to_db,
+        };
+        let mut attempt = 0;
+        loop {
+            let e = match driver::connect(&db).await {
+                Ok(client) => return Ok(client),
+                Err(e) => e.connect(db.label()),
+            };
+            attempt += 1;
+            let wait = self.retry.backoff(attempt, &e).ok_or(e)?;
+            eprintln!("connect {}: retrying in {:?}", db.label(), wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub(crate) fn config(&self, direct: Direct) -> &DatabaseConfig {
+        match direct {
+            Direct::FROM => &self.from_db,
+            Direct::TO => &self.to_db,
+        }
+    }
+
+    pub fn dialect(&self, direct: Direct) -> Dialect {
+        let db = match direct {
+            Direct::FROM => &self.from_db,
+            Direct::TO => &self.to_db,
+        };
+        match db.class.as_str() {
+            "MSSQL" => Dialect::Mssql,
+            "POSTGRES" => Dialect::Postgres,
+            "SQLITE" => Dialect::Sqlite,
+            _ => Dialect::Mysql,
+        }
+    }
+
+    pub fn get_default_db(self, direct: Direct) -> String {
+        let db = match direct {
+            Direct::FROM => self.from_db,
+            Direct::TO => self.to_db,
+        };
+        match db.class.as_str() {
+            "POSTGRES" => db.schema,
+            "SQLITE" => "main".to_string(),
+            _ => db.default_db,
+        }
+    }
+
+    pub async fn show_dbs(self, direct: Direct) -> Result<Vec<String>> {
+        let mut client = self.client(direct).await?;
+        let res = client.show_dbs().await;
+        client.close().await?;
+        res
+    }
+
+    pub async fn show_tables(self, direct: Direct, ddb: String) -> Result<Vec<String>> {
+        let mut client = self.client(direct).await?;
+        let res = client.show_tables(&ddb).await;
+        client.close().await?;
+        res
+    }
+
+    pub async fn table_exists(self, direct: Direct, ddb: String, table: String) -> Result<bool> {
+        let mut client = self.client(direct).await?;
+        let res = client.table_exists(&ddb, &table).await;
+        client.close().await?;
+        res
+    }
+
+    pub async fn query(self, direct: Direct, sql_str: String) -> Result<Vec<Vec<Value>>> {
+        let mut client = self.client(direct).await?;
+        let res = client.query(&sql_str).await;
+        client.close().await?;
+        res
+    }
+
+    pub async fn execute(self, direct: Direct, statements: Vec<String>) -> Result<()> {
+        let mut client = self.client(direct).await?;
+        let res = client.execute(&statements).await;
+        client.close().await?;
+        res
+    }
+
+    /// Creates `table` on the other side of `direct` from the source column
+    /// list when it does not exist there yet.
+    pub async fn ensure_table(
+        self,
+        direct: Direct,
+        from_ddb: String,
+        to_ddb: String,
+        table: String,
+    ) -> Result<bool> {
+        let target = direct.toggle();
+        let to_table = self.dialect(target).local_table(&table);
+        if self
+            .clone()
+            .table_exists(target, to_ddb.clone(), to_table.clone())
+            .await?
+        {
+            return Ok(false);
+        }
+
+        let columns = self
+            .clone()
+            .table_detail(direct, from_ddb, table.clone())
+            .await?;
+        if columns.is_empty() {
+            return Err(Error::Query("table not found or has no columns".into()));
+        }
+        let (from, to) = (self.dialect(direct), self.dialect(target));
+        let (_, table_name) = from.split_table(&table);
+        let mut mapped = vec![];
+        let mut unmapped = vec![];
+        for column in columns {
+            match self.type_map.map(from, to, table_name, &column) {
+                Ok(t) => mapped.push((column, t)),
+                Err(e) => unmapped.push(e),
+            }
+        }
+        if !unmapped.is_empty() {
+            return Err(Error::Mapping(unmapped.join("\n")));
+        }
+
+        for (column, data_type) in &mapped {
+            if let Err(default) = ddl::default_clause(to, column, data_type) {
+                eprintln!(
+                    "{}: default {} of {} is not carried over",
+                    table, default, column.name
+                );
+            }
+        }
+        let statements = ddl::create_table(to, &to_ddb, &to_table, &mapped);
+        self.execute(target, statements).await?;
+        Ok(true)
+    }
+
+    pub async fn table_detail(
+        self,
+        direct: Direct,
+        ddb: String,
+        table: String,
+    ) -> Result<Vec<ColumnDetail>> {
+        let mut client = self.client(direct).await?;
+        let res = client.table_detail(&ddb, &table).await;
+        client.close().await?;
+        res
+    }
+
+    /// Splits `table` into the key ranges it is copied in, one unless it is
+    /// chunked. Tables whose key holds integers or dates are read in key
+    /// order, so an interrupted copy can resume from the last key written.
+    pub(crate) async fn plan(
+        self,
+        direct: Direct,
+        ddb: &str,
+        table: &str,
+        detail: &[ColumnDetail],
+    ) -> Result<Progress> {
+        let (count, key) = self.chunks.get(table);
+        let explicit = key.is_some();
+        let whole = Progress {
+            key: None,
+            kind: KeyKind::default(),
+            chunks: vec![Chunk::new(KeyRange::Between(None, None))],
+            owned: false,
+        };
+        let column = match key {
+            Some(key) => detail.iter().find(|x| x.name.eq_ignore_ascii_case(&key)),
+            None => detail.iter().find(|x| x.primary_key),
+        };
+        let column = match column {
+            Some(column) => column,
+            None if explicit => {
+                return Err(Error::config(
+                    "CHUNKS",
+                    &format!("{}.key", table),
+                    "no such column",
+                ))
+            }
+            None => {
+                if count > 1 {
+                    eprintln!(
+                        "{}: no primary key to chunk by, reading in one piece",
+                        table
+                    );
+                }
+                return Ok(whole);
+            }
+        };
+
+        let dialect = self.dialect(direct);
+        let quoted = dialect.quote(&column.name);
+        let mut client = self.client(direct).await?;
+        let res = client
+            .query(&format!(
+                "SELECT MIN({}), MAX({}) FROM {}",
+                quoted,
+                quoted,
+                dialect.table_name(ddb, table)
+            ))
+            .await;
+        client.close().await?;
+        // text keys such as `000123` order as text, not as the numbers they read as
+        let textual = textual(&column.data_type);
+        let (kind, low, high) = match res?.first().map(|x| x.as_slice()) {
+            Some([Value::Null, _]) | Some([_, Value::Null]) => return Ok(whole),
+            Some([low, high]) => match (key_point(low), key_point(high)) {
+                (Some((kind, low)), Some((_, high))) if !textual => (kind, low, high),
+                _ if explicit => {
+                    return Err(Error::config(
+                        "CHUNKS",
+                        &format!("{}.key", table),
+                        format!("{} is not an integer or date", column.name),
+                    ))
+                }
+                _ => {
+                    if count > 1 {
+                        eprintln!(
+                            "{}: key {} is not an integer or date, reading in one piece",
+                            table, column.name
+                        );
+                    }
+                    return Ok(whole);
+                }
+            },
+            _ => return Ok(whole),
+        };
+
+        let count = count.max(1);
+        let step = ((high as i128 - low as i128) / count as i128 + 1) as i64;
+        let mut chunks = vec![];
+        if column.nullable {
+            chunks.push(Chunk::new(KeyRange::Null));
+        }
+        let mut start = None;
+        for bound in (1..count as i64).map(|i| low + step * i) {
+            if bound > high {
+                break;
+            }
+            chunks.push(Chunk::new(KeyRange::Between(start, Some(bound))));
+            start = Some(bound);
+        }
+        chunks.push(Chunk::new(KeyRange::Between(start, None)));
+        Ok(Progress {
+            key: Some(column.name.clone()),
+            kind,
+            chunks,
+            owned: false,
+        })
+    }
+
+    /// Rows of `table` an incremental copy reads, or `None` when there is
+    /// nothing new. The highest value is saved as pending before copying, so
+    /// a resumed copy reads the same rows.
+    #[allow(clippy::too_many_arguments)]
+    async fn window(
+        self,
+        direct: Direct,
+        ddb: &str,
+        table: &str,
+        detail: &[ColumnDetail],
+        id: &str,
+        column: &str,
+        resumed: bool,
+        inclusive: bool,
+    ) -> Result<Option<Window>> {
+        let column = match detail.iter().find(|x| x.name.eq_ignore_ascii_case(column)) {
+            Some(x) => x.name.clone(),
+            None => return Err(Error::config("INCREMENTAL", table, "no such column")),
+        };
+        let stored = match self.watermarks.get(id) {
+            Some(x) if x.column == column => Some(x),
+            Some(x) => {
+                eprintln!(
+                    "{}: watermark column changed from {}, copying in full",
+                    table, x.column
+                );
+                None
+            }
+            None => None,
+        };
+        if let Some(Watermark {
+            value,
+            pending: Some(pending),
+            ..
+        }) = stored.clone().filter(|_| resumed)
+        {
+            return Ok(Some(Window {
+                column,
+                low: value,
+                high: pending,
+                inclusive,
+            }));
+        }
+
+        let dialect = self.dialect(direct);
+        let rows = self
+            .clone()
+            .query(
+                direct,
+                format!(
+                    "SELECT MAX({}) FROM {}",
+                    dialect.quote(&column),
+                    dialect.table_name(ddb, table)
+                ),
+            )
+            .await?;
+        let high = rows
+            .first()
+            .and_then(|x| x.first())
+            .and_then(Mark::from_value);
+        let high = match high {
+            Some(high) => high,
+            None => return Ok(None),
+        };
+        let low = stored.and_then(|x| x.value);
+        if low.as_ref() == Some(&high) && !inclusive {
+            return Ok(None);
+        }
+        self.watermarks.set(
+            id,
+            Watermark {
+                column: column.clone(),
+                value: low.clone(),
+                pending: Some(high.clone()),
+            },
+        )?;
+        Ok(Some(Window {
+            column,
+            low,
+            high,
+            inclusive,
+        }))
+    }
+
+    /// Columns rows of `table` on the `direct` side are matched by: those
+    /// from `[WRITEMODE]`, or else the primary key. Empty when there is none.
+    pub(crate) async fn key_columns(
+        self,
+        direct: Direct,
+        ddb: &str,
+        table: &str,
+    ) -> Result<Vec<String>> {
+        if let (_, Some(key)) = self.write_modes.get(table) {
+            return Ok(key);
+        }
+        Ok(self
+            .table_detail(direct, ddb.to_string(), table.to_string())
+            .await?
+            .into_iter()
+            .filter(|x| x.primary_key)
+            .map(|x| x.name)
+            .collect::<Vec<String>>())
+    }
+
+    /// Write mode of `table` on the `direct` side and what writing it does on
+    /// a duplicate key.
+    async fn on_conflict(
+        self,
+        direct: Direct,
+        ddb: &str,
+        table: &str,
+    ) -> Result<(WriteMode, OnConflict)> {
+        let (mode, _) = self.write_modes.get(table);
+        if matches!(mode, WriteMode::Append | WriteMode::Truncate) {
+            return Ok((mode, OnConflict::Fail));
+        }
+        let key = self.key_columns(direct, ddb, table).await?;
+        if key.is_empty() {
+            return Err(Error::config(
+                "WRITEMODE",
+                &format!("{}.key", table),
+                format!("{} has no primary key to {} by", table, mode),
+            ));
+        }
+        let on_conflict = match mode {
+            WriteMode::Upsert => OnConflict::Update(key),
+            _ => OnConflict::Skip(key),
+        };
+        Ok((mode, on_conflict))
+    }
+
+    /// Streams the rows of one chunk from the `direct` side to the other one
+    /// over connections of its own, checkpointing after every batch. Returns
+    /// the rows the chunk holds in the target. A batch failing with a
+    /// transient error is written again as a whole, on a new connection.
+    async fn copy_rows(
+        self,
+        direct: Direct,
+        from_ddb: String,
+        to_ddb: String,
+        table: String,
+        columns: Arc<Vec<String>>,
+        piece: Piece,
+    ) -> Result<u64> {
+        let batch_size = self.batch_size.get(&table);
+        let (checkpoints, retry) = (self.checkpoints.clone(), self.retry);
+        let mut source = self.clone().client(direct).await?;
+        let mut target = self.clone().client(direct.toggle()).await?;
+        let (tx, mut rx) = mpsc::channel(BATCH_QUEUE);
+        let read = source.read_rows(&from_ddb, &table, &columns, &piece.filter, batch_size, tx);
+        let write = async {
+            let mut rows = piece.rows;
+            let mut last = piece.last;
+            while let Some(batch) = rx.recv().await {
+                let points = match piece.key {
+                    Some(idx) => batch
+                        .iter()
+                        .map(|row| key_point(&row[idx]).map(|x| x.1))
+                        .collect::<Vec<Option<i64>>>(),
+                    None => vec![],
+                };
+                let mut attempt = 0;
+                let written = loop {
+                    let e = match target
+                        .write_rows(&to_ddb, &table, &columns, batch.clone(), &piece.on_conflict)
+                        .await
+                    {
+                        Ok(written) => break written,
+                        Err(e) => e,
+                    };
+                    attempt += 1;
+                    let wait = match retry.backoff(attempt, &e) {
+                        Some(wait) => wait,
+                        None => return Err(e),
+                    };
+                    eprintln!("{}: {}, retrying in {:?}", table, e, wait);
+                    tokio::time::sleep(wait).await;
+                    // drivers write a batch atomically; a fresh session also
+                    // drops an open transaction, stage rows or identity insert
+                    target = self.clone().client(direct.toggle()).await?;
+                };
+                // rows sharing the last key may continue in the next batch,
+                // so a resume starts again at that key
+                if let Some(Some(point)) = points.last().copied() {
+                    if last != Some(point) {
+                        let below = points.iter().filter(|x| x.is_none_or(|x| x < point));
+                        checkpoints.advance(
+                            &piece.id,
+                            piece.idx,
+                            point,
+                            rows + below.count() as u64,
+                        )?;
+                        last = Some(point);
+                    }
+                }
+                rows += written;
+            }
+            checkpoints.finish_chunk(&piece.id, piece.idx, rows)?;
+            Ok(rows)
+        };
+        let res = tokio::try_join!(read, write).map(|(_, rows)| rows);
+        source.close().await?;
+        target.close().await?;
+        res
+    }
+
+    /// Copies every row of `table` from the `direct` side into the same table on
+    /// the other side, returning the number of rows written. Chunked tables
+    /// copy their key ranges in parallel, at most `workers` at a time. When
+    /// resuming, the rows a chunk wrote after its last checkpoint are deleted
+    /// from the target and copied again, or overwritten in upsert modes.
+    /// Deleting is only done in tables this copy created or emptied.
+    pub async fn transfer_table(
+        self,
+        direct: Direct,
+        from_ddb: String,
+        to_ddb: String,
+        table: String,
+    ) -> Result<u64> {
+        let created = self
+            .clone()
+            .ensure_table(direct, from_ddb.clone(), to_ddb.clone(), table.clone())
+            .await?;
+
+        let detail = self
+            .clone()
+            .table_detail(direct, from_ddb.clone(), table.clone())
+            .await?;
+        let columns = Arc::new(
+            detail
+                .iter()
+                .map(|x| x.name.clone())
+                .collect::<Vec<String>>(),
+        );
+        if columns.is_empty() {
+            return Ok(0);
+        }
+
+        let (mode, on_conflict) = self
+            .clone()
+            .on_conflict(direct.toggle(), &to_ddb, &table)
+            .await?;
+
+        let id = self.checkpoint_id(direct, &from_ddb, &to_ddb, &table);
+        let resumed = match self.checkpoints.get(&id) {
+            Some(progress) if self.resume => Some(progress),
+            Some(_) => {
+                eprintln!("{}: discarding unfinished checkpoint", table);
+                None
+            }
+            None => None,
+        };
+        let window = match self.incremental.get(&table) {
+            Some(column) => {
+                let window = self
+                    .clone()
+                    .window(
+                        direct,
+                        &from_ddb,
+                        &table,
+                        &detail,
+                        &id,
+                        &column,
+                        resumed.is_some(),
+                        on_conflict != OnConflict::Fail,
+                    )
+                    .await?;
+                if window.is_none() {
+                    return Ok(0);
+                }
+                window
+            }
+            None => None,
+        };
+        let mut progress = match resumed.clone() {
+            Some(progress) if progress.key.is_none() => {
+                return Err(Error::Checkpoint(
+                    "no key to resume from, copy it again without resuming".to_string(),
+                ))
+            }
+            Some(progress) => progress,
+            None => {
+                self.clone()
+                    .plan(direct, &from_ddb, &table, &detail)
+                    .await?
+            }
+        };
+        let (from, to) = (self.dialect(direct), self.dialect(direct.toggle()));
+        if resumed.is_none() {
+            progress.owned = created;
+        }
+        if mode == WriteMode::Truncate && resumed.is_none() {
+            if window.as_ref().is_some_and(|x| x.low.is_some()) {
+                eprintln!("{}: not truncating, only new rows are copied", table);
+            } else {
+                progress.owned = true;
+                let verb = match to {
+                    Dialect::Sqlite => "DELETE FROM",
+                    _ => "TRUNCATE TABLE",
+                };
+                self.clone()
+                    .execute(
+                        direct.toggle(),
+                        vec![format!("{} {}", verb, to.table_name(&to_ddb, &table))],
+                    )
+                    .await?;
+            }
+        }
+        self.checkpoints.start(&id, progress.clone())?;
+
+        let key = progress
+            .key
+            .as_ref()
+            .and_then(|key| columns.iter().position(|x| x == key));
+        let mut written = 0;
+        let mut pieces = vec![];
+        for (idx, chunk) in progress.chunks.iter().enumerate() {
+            if chunk.done {
+                written += chunk.rows;
+                continue;
+            }
+            let condition = |dialect, from| {
+                let range = progress
+                    .key
+                    .as_ref()
+                    .and_then(|key| chunk.range.condition(dialect, key, progress.kind, from));
+                let window = window.as_ref().map(|x| x.condition(dialect));
+                match (range, window) {
+                    (Some(range), Some(window)) => Some(format!("{} AND {}", range, window)),
+                    (range, window) => range.or(window),
+                }
+            };
+            let restart = chunk.range == KeyRange::Null && chunk.rows > 0;
+            // upserts match the rows copied again by key instead
+            let rewrite = resumed.is_some()
+                && (chunk.last.is_some() || restart)
+                && !matches!(mode, WriteMode::Upsert | WriteMode::InsertIgnore);
+            if rewrite && !progress.owned {
+                return Err(Error::Checkpoint(format!(
+                    "{} held rows before the copy started, which resuming would delete; \
+                     copy it again without resuming, or resume in upsert mode",
+                    table
+                )));
+            }
+            if rewrite {
+                if let Some(condition) = condition(to, chunk.last) {
+                    self.clone()
+                        .execute(
+                            direct.toggle(),
+                            vec![format!(
+                                "DELETE FROM {} WHERE {}",
+                                to.table_name(&to_ddb, &table),
+                                condition
+                            )],
+                        )
+                        .await?;
+                }
+            }
+            pieces.push(Piece {
+                id: id.clone(),
+                idx,
+                filter: Filter {
+                    condition: condition(from, chunk.last),
+                    order_by: match (key, chunk.range) {
+                        (Some(_), KeyRange::Between(..)) => {
+                            progress.key.as_ref().map(|x| from.quote(x))
+                        }
+                        _ => None,
+                    },
+                },
+                key: key.filter(|_| chunk.range != KeyRange::Null),
+                last: chunk.last,
+                rows: if restart { 0 } else { chunk.rows },
+                on_conflict: on_conflict.clone(),
+            });
+        }
+
+        let semaphore = Arc::new(Semaphore::new(self.workers().min(pieces.len()).max(1)));
+        let tasks = pieces
+            .into_iter()
+            .map(|piece| {
+                let db_meta = self.clone();
+                let semaphore = semaphore.clone();
+                let (from_ddb, to_ddb, table) = (from_ddb.clone(), to_ddb.clone(), table.clone());
+                let columns = columns.clone();
+                async move {
+                    let _permit = semaphore.acquire().await?;
+                    db_meta
+                        .copy_rows(direct, from_ddb, to_ddb, table, columns, piece)
+                        .await
+                }
+            })
+            .collect::<Vec<_>>();
+        written += join_all(tasks).await?.into_iter().sum::<u64>();
+        self.checkpoints.remove(&id)?;
+        if window.is_some() {
+            self.watermarks.commit(&id)?;
+        }
+        Ok(written)
+    }
+
+    /// Deletes the rows of `table` on the other side of `direct` that
+    /// `[DELETES]` finds deleted at the source, or sets their mark column.
+    /// Returns how many rows that touched, or `None` when the table is not
+    /// listed.
+    pub async fn propagate_deletes(
+        self,
+        direct: Direct,
+        from_ddb: String,
+        to_ddb: String,
+        table: String,
+    ) -> Result<Option<u64>> {
+        let (check, mark) = match self.deletes.get(&table) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let target = direct.toggle();
+        let dialect = self.dialect(target);
+        let detail = self
+            .clone()
+            .table_detail(target, to_ddb.clone(), table.clone())
+            .await?;
+        let column = |name: &str, key: String| {
+            detail
+                .iter()
+                .find(|x| x.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    Error::config(
+                        "DELETES",
+                        &key,
+                        format!("{} has no column {} in the target", table, name),
+                    )
+                })
+        };
+        let mark = match &mark {
+            Some(name) => {
+                let column = column(name, format!("{}.mark", table))?;
+                Some((dialect.quote(&column.name), flag(dialect, column)))
+            }
+            None => None,
+        };
+        let table_name = dialect.table_name(&to_ddb, &table);
+        let remove = |condition| {
+            self.clone()
+                .remove_rows(target, table_name.clone(), condition, mark.clone())
+        };
+        let deleted = match check {
+            DeleteCheck::Flag(name) => {
+                remove(flag(dialect, column(&name, table.clone())?).0).await?
+            }
+            DeleteCheck::Keys => {
+                let key = self.clone().key_columns(target, &to_ddb, &table).await?;
+                if key.is_empty() {
+                    return Err(Error::config(
+                        "DELETES",
+                        &table,
+                        format!("{} has no primary key to compare by", table),
+                    ));
+                }
+                let (tx, mut rx) = mpsc::channel(BATCH_QUEUE);
+                let find = diff::deleted_keys(
+                    self.clone(),
+                    direct,
+                    from_ddb,
+                    to_ddb.clone(),
+                    table.clone(),
+                    key.clone(),
+                    tx,
+                );
+                // keys are removed while the rest of the table is compared
+                let apply = async {
+                    let mut deleted = 0;
+                    let mut keys = vec![];
+                    loop {
+                        let batch = rx.recv().await;
+                        let done = batch.is_none();
+                        keys.extend(batch.unwrap_or_default());
+                        while keys.len() >= DELETE_BATCH || (done && !keys.is_empty()) {
+                            let rest = keys.split_off(keys.len().min(DELETE_BATCH));
+                            deleted += remove(key_condition(dialect, &key, &keys)).await?;
+                            keys = rest;
+                        }
+                        if done {
+                            return Ok(deleted);
+                        }
+                    }
+                };
+                tokio::try_join!(find, apply)?.1
+            }
+        };
+        Ok(Some(deleted))
+    }
+
+    /// Deletes the rows of `table_name` on the `target` side that `condition`
+    /// selects, or sets `mark` on them, returning how many there were.
+    async fn remove_rows(
+        self,
+        target: Direct,
+        table_name: String,
+        condition: String,
+        mark: Option<(String, (String, String))>,
+    ) -> Result<u64> {
+        // rows marked before are left alone and not counted again
+        let condition = match &mark {
+            Some((name, (set, _))) => {
+                format!("({}) AND ({} IS NULL OR NOT ({}))", condition, name, set)
+            }
+            None => condition,
+        };
+        let dialect = self.dialect(target);
+        let count = self
+            .clone()
+            .query(
+                target,
+                format!(
+                    "SELECT {} FROM {} WHERE {}",
+                    dialect.count("*"),
+                    table_name,
+                    condition
+                ),
+            )
+            .await?
+            .first()
+            .and_then(|x| x.first())
+            .and_then(Value::canonical)
+            .and_then(|x| x.parse::<u64>().ok())
+            .unwrap_or(0);
+        if count == 0 {
+            return Ok(0);
+        }
+        let statement = match &mark {
+            Some((name, (_, value))) => format!(
+                "UPDATE {} SET {} = {} WHERE {}",
+                table_name, name, value, condition
+            ),
+            None => format!("DELETE FROM {} WHERE {}", table_name, condition),
+        };
+        self.execute(target, vec![statement]).await?;
+        Ok(count)
+    }
+
+    /// Compares `table` on the other side of `direct` with its source as
+    /// `[VERIFY]` asks, returning the differences found, or `None` when
+    /// verification is off.
+    pub async fn verify_table(
+        self,
+        direct: Direct,
+        from_ddb: String,
+        to_ddb: String,
+        table: String,
+    ) -> Result<Option<Vec<String>>> {
+        if self.verify == VerifyMode::Off {
+            return Ok(None);
+        }
+        let (detail, other) = tokio::try_join!(
+            self.clone()
+                .table_detail(direct, from_ddb.clone(), table.clone()),
+            self.clone()
+                .table_detail(direct.toggle(), to_ddb.clone(), table.clone()),
+        )?;
+        let compare = Compare::columns(&detail, &other);
+
+        // label, aggregate, column and comparison of every figure both sides
+        // must agree on
+        let mut checks = vec![("rows".to_string(), "COUNT", None, Compare::default())];
+        if self.verify != VerifyMode::Count {
+            for (column, compare) in detail.iter().zip(&compare) {
+                let name = Some(column.name.as_str());
+                checks.push((
+                    format!("{} non-null", column.name),
+                    "COUNT",
+                    name,
+                    Compare::default(),
+                ));
+                if comparable(&column.data_type) {
+                    checks.push((format!("{} min", column.name), "MIN", name, *compare));
+                    checks.push((format!("{} max", column.name), "MAX", name, *compare));
+                }
+            }
+        }
+        let sql = |dialect: Dialect, ddb: &str| {
+            let exprs = checks
+                .iter()
+                .map(|(_, func, column, _)| {
+                    let arg = column.map_or("*".to_string(), |x| dialect.quote(x));
+                    match *func {
+                        "COUNT" => dialect.count(&arg),
+                        func => format!("{}({})", func, arg),
+                    }
+                })
+                .collect::<Vec<String>>();
+            format!(
+                "SELECT {} FROM {}",
+                exprs.join(", "),
+                dialect.table_name(ddb, &table)
+            )
+        };
+        let (from, to) = (self.dialect(direct), self.dialect(direct.toggle()));
+        let (source, target) = tokio::try_join!(
+            self.clone().query(direct, sql(from, &from_ddb)),
+            self.clone().query(direct.toggle(), sql(to, &to_ddb)),
+        )?;
+
+        let first = |rows: Vec<Vec<Value>>| rows.into_iter().next().unwrap_or_default();
+        let show = |x: &Value| x.canonical().unwrap_or_else(|| "null".to_string());
+        let mut mismatches = vec![];
+        for ((label, _, _, compare), (a, b)) in checks
+            .iter()
+            .zip(first(source).iter().zip(first(target).iter()))
+        {
+            if compare.text(a) != compare.text(b) {
+                mismatches.push(format!(
+                    "{}: {} in source, {} in target",
+                    label,
+                    show(a),
+                    show(b)
+                ));
+            }
+        }
+
+        if self.verify == VerifyMode::Hash {
+            let columns = Arc::new(
+                detail
+                    .iter()
+                    .map(|x| x.name.clone())
+                    .collect::<Vec<String>>(),
+            );
+            let compare = Arc::new(compare);
+            let (source, target) = tokio::try_join!(
+                self.clone().row_hash(
+                    direct,
+                    from_ddb,
+                    table.clone(),
+                    columns.clone(),
+                    compare.clone(),
+                    Filter::default()
+                ),
+                self.clone().row_hash(
+                    direct.toggle(),
+                    to_ddb,
+                    table.clone(),
+                    columns,
+                    compare,
+                    Filter::default()
+                ),
+            )?;
+            if source != target {
+                mismatches.push("row hash differs".to_string());
+            }
+        }
+        Ok(Some(mismatches))
+    }
+
+    /// Sum of the hashes of the rows of `table` on the `direct` side that
+    /// `filter` selects, which does not depend on the order the backend
+    /// returns the rows in. Values are hashed as `compare` reads them.
+    pub(crate) async fn row_hash(
+        self,
+        direct: Direct,
+        ddb: String,
+        table: String,
+        columns: Arc<Vec<String>>,
+        compare: Arc<Vec<Compare>>,
+        filter: Filter,
+    ) -> Result<u64> {
+        let batch_size = self.batch_size.get(&table);
+        let mut client = self.client(direct).await?;
+        let (tx, mut rx) = mpsc::channel(BATCH_QUEUE);
+        let read = client.read_rows(&ddb, &table, &columns, &filter, batch_size, tx);
+        let hash = async {
+            let mut sum = 0u64;
+            while let Some(batch) = rx.recv().await {
+                for row in batch {
+                    let mut hasher = DefaultHasher::new();
+                    for (value, compare) in row.iter().zip(compare.iter()) {
+                        compare.text(value).hash(&mut hasher);
+                    }
+                    sum = sum.wrapping_add(hasher.finish());
+                }
+            }
+            Ok(sum)
+        };
+        let res = tokio::try_join!(read, hash).map(|(_, sum)| sum);
+        client.close().await?;
+        res
+    }
+}
+
+/// One chunk as handed to `DatabaseMeta::copy_rows`.
+struct Piece {
+    /// checkpoint id of the table
+    id: String,
+    idx: usize,
+    filter: Filter,
+    /// position of the key among the columns
+    key: Option<usize>,
+    last: Option<i64>,
+    rows: u64,
+    on_conflict: OnConflict,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(data_type: &str, column_type: &str) -> ColumnDetail {
+        ColumnDetail {
+            name: "c".to_string(),
+            data_type: data_type.to_string(),
+            column_type: column_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn sized(data_type: &str, len: i64) -> ColumnDetail {
+        ColumnDetail {
+            max_length: Some(len),
+            ..column(data_type, &format!("{}({})", data_type, len))
+        }
+    }
+
+    fn decimal(data_type: &str, precision: i64, scale: i64) -> ColumnDetail {
+        ColumnDetail {
+            precision: Some(precision),
+            scale: Some(scale),
+            ..column(data_type, data_type)
+        }
+    }
+
+    #[test]
+    fn mssql_to_mysql_types() {
+        let map = |c: &ColumnDetail| mssql_to_mysql(c);
+        assert_eq!(map(&column("bit", "bit")).as_deref(), Some("tinyint(1)"));
+        assert_eq!(
+            map(&column("tinyint", "tinyint")).as_deref(),
+            Some("tinyint unsigned")
+        );
+        assert_eq!(map(&sized("nchar", 10)).as_deref(), Some("char(10)"));
+        assert_eq!(
+            map(&sized("nvarchar", 300)).as_deref(),
+            Some("varchar(300)")
+        );
+        assert_eq!(map(&sized("nvarchar", -1)).as_deref(), Some("longtext"));
+        assert_eq!(map(&sized("varbinary", -1)).as_deref(), Some("longblob"));
+        assert_eq!(
+            map(&column("datetime", "datetime")).as_deref(),
+            Some("datetime(3)")
+        );
+        assert_eq!(
+            map(&decimal("decimal", 18, 2)).as_deref(),
+            Some("decimal(18,2)")
+        );
+        assert_eq!(map(&column("geography", "geography")), None);
+    }
+
+    #[test]
+    fn mysql_to_mssql_widens_unsigned() {
+        let map = |c: &ColumnDetail| mysql_to_mssql(c);
+        assert_eq!(
+            map(&column("tinyint", "tinyint unsigned")).as_deref(),
+            Some("tinyint")
+        );
+        assert_eq!(
+            map(&column("tinyint", "tinyint")).as_deref(),
+            Some("smallint")
+        );
+        assert_eq!(
+            map(&column("int", "int unsigned")).as_deref(),
+            Some("bigint")
+        );
+        assert_eq!(
+            map(&column("bigint", "bigint unsigned")).as_deref(),
+            Some("decimal(20,0)")
+        );
+        assert_eq!(
+            map(&sized("varchar", 5000)).as_deref(),
+            Some("nvarchar(max)")
+        );
+        assert_eq!(map(&decimal("decimal", 65, 0)), None);
+    }
+
+    #[test]
+    fn type_map_overrides_defaults() {
+        let mut map = TypeMap::default();
+        map.types
+            .insert("nvarchar".to_string(), "varchar({len})".to_string());
+        map.columns
+            .insert("orders.remark".to_string(), "text".to_string());
+        let remark = ColumnDetail {
+            name: "Remark".to_string(),
+            ..sized("nvarchar", 50)
+        };
+        let map_to_mysql =
+            |table: &str, c: &ColumnDetail| map.map(Dialect::Mssql, Dialect::Mysql, table, c);
+        assert_eq!(map_to_mysql("Orders", &remark), Ok("text".to_string()));
+        assert_eq!(
+            map_to_mysql("Items", &remark),
+            Ok("varchar(50)".to_string())
+        );
+        assert_eq!(
+            map_to_mysql("Items", &column("bit", "bit")),
+            Ok("tinyint(1)".to_string())
+        );
+        assert!(map_to_mysql("Items", &column("geography", "geography"))
+            .unwrap_err()
+            .contains("[COLUMNMAP]"));
+    }
+
+    #[test]
+    fn postgres_mappings() {
+        assert_eq!(
+            mysql_to_postgres(&column("tinyint", "tinyint(1)")).as_deref(),
+            Some("boolean")
+        );
+        assert_eq!(
+            mysql_to_postgres(&column("bigint", "bigint unsigned")).as_deref(),
+            Some("numeric(20,0)")
+        );
+        assert_eq!(
+            mssql_to_postgres(&column("uniqueidentifier", "uniqueidentifier")).as_deref(),
+            Some("uuid")
+        );
+        assert_eq!(
+            postgres_to_mysql(&sized("character varying", 20)).as_deref(),
+            Some("varchar(20)")
+        );
+        assert_eq!(
+            postgres_to_mssql(&column("timestamp with time zone", "timestamptz")).as_deref(),
+            Some("datetimeoffset(0)")
+        );
+        assert_eq!(postgres_to_mysql(&column("tsvector", "tsvector")), None);
+        // the same backend on both sides keeps the declaration
+        assert_eq!(
+            TypeMap::default().map(
+                Dialect::Postgres,
+                Dialect::Postgres,
+                "t",
+                &column("int4", "int4")
+            ),
+            Ok("int4".to_string())
+        );
+    }
+
+    #[test]
+    fn mysql_to_sqlite_drops_display_width() {
+        assert_eq!(
+            mysql_to_sqlite(&column("int", "int(10) unsigned")),
+            "int unsigned"
+        );
+        assert_eq!(
+            mysql_to_sqlite(&column("tinyint", "tinyint(1)")),
+            "tinyint(1)"
+        );
+        assert_eq!(mysql_to_sqlite(&sized("varchar", 40)), "varchar(40)");
+    }
+
+    /// Source and target sqlite files in a directory of their own, which also
+    /// holds the checkpoints.
+    fn sqlite_meta(name: &str) -> (DatabaseMeta, std::path::PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("sql-transfer-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let sqlite = |file: &str| DatabaseConfig {
+            class: "SQLITE".to_string(),
+            path: dir.join(file).to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        let db_meta = DatabaseMeta {
+            from_db: sqlite("from.db"),
+            to_db: sqlite("to.db"),
+            checkpoints: Checkpoints::load_from(dir.join("checkpoint.ini")),
+            ..Default::default()
+        };
+        (db_meta, dir)
+    }
+
+    /// Creates `orders` with `rows` rows on the `direct` side.
+    async fn seed_orders(db_meta: &DatabaseMeta, direct: Direct, rows: i64) {
+        let mut statements = vec![
+            "CREATE TABLE orders (id integer PRIMARY KEY, name varchar(20), \
+             amount decimal(10,2), placed datetime, note text)"
+                .to_string(),
+        ];
+        for id in 1..=rows {
+            statements.push(format!(
+                "INSERT INTO orders VALUES ({}, 'name\t{}', {}.25, '2024-01-02 03:04:{:02}', {})",
+                id,
+                id,
+                id,
+                id % 60,
+                if id % 7 == 0 { "NULL" } else { "'it''s'" }
+            ));
+        }
+        db_meta.clone().execute(direct, statements).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_transfer() {
+        let (db_meta, dir) = sqlite_meta("transfer");
+        seed_orders(&db_meta, Direct::FROM, 2500).await;
+        let rows = db_meta
+            .clone()
+            .transfer_table(
+                Direct::FROM,
+                "main".to_string(),
+                "main".to_string(),
+                "orders".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows, 2500);
+        let all = || "SELECT id, name, amount, placed, note FROM orders ORDER BY id".to_string();
+        let source = db_meta.clone().query(Direct::FROM, all()).await.unwrap();
+        let target = db_meta.clone().query(Direct::TO, all()).await.unwrap();
+        assert_eq!(target.len(), 2500);
+        assert_eq!(source, target);
+        // a finished copy leaves no checkpoint behind
+        assert!(!dir.join("checkpoint.ini").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn key_range_parse() {
+        assert_eq!(KeyRange::parse("null"), Some(KeyRange::Null));
+        assert_eq!(
+            KeyRange::parse("1..10"),
+            Some(KeyRange::Between(Some(1), Some(10)))
+        );
+        assert_eq!(
+            KeyRange::parse("..-5"),
+            Some(KeyRange::Between(None, Some(-5)))
+        );
+        assert_eq!(KeyRange::parse(".."), Some(KeyRange::Between(None, None)));
+        assert_eq!(KeyRange::parse("1-10"), None);
+        assert_eq!(KeyRange::parse("a..b"), None);
+        for range in [KeyRange::Null, KeyRange::Between(Some(3), None)] {
+            assert_eq!(KeyRange::parse(&range.to_text()), Some(range));
+        }
+    }
+
+    #[test]
+    fn key_range_condition() {
+        let range = KeyRange::Between(Some(1), Some(10));
+        assert_eq!(
+            range.condition(Dialect::Mysql, "id", KeyKind::Int, None),
+            Some("`id` >= 1 AND `id` < 10".to_string())
+        );
+        // resuming starts at the last key written
+        assert_eq!(
+            range.condition(Dialect::Mssql, "id", KeyKind::Int, Some(4)),
+            Some("[id] >= 4 AND [id] < 10".to_string())
+        );
+        assert_eq!(
+            KeyRange::Null.condition(Dialect::Postgres, "id", KeyKind::Int, None),
+            Some("\"id\" IS NULL".to_string())
+        );
+        assert_eq!(
+            KeyRange::Between(None, None).condition(Dialect::Sqlite, "id", KeyKind::Int, None),
+            None
+        );
+        let day = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .num_days_from_ce() as i64;
+        assert_eq!(
+            KeyRange::Between(None, Some(day)).condition(Dialect::Mssql, "d", KeyKind::Date, None),
+            Some("[d] < N'2024-03-01'".to_string())
+        );
+        assert_eq!(
+            KeyRange::Between(Some(86400), None).condition(
+                Dialect::Mssql,
+                "t",
+                KeyKind::DateTime,
+                None
+            ),
+            Some("[t] >= N'1970-01-02T00:00:00'".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn sqlite_verify() {
+        let (mut db_meta, dir) = sqlite_meta("verify");
+        seed_orders(&db_meta, Direct::FROM, 500).await;
+        seed_orders(&db_meta, Direct::TO, 500).await;
+        let verify = |db_meta: DatabaseMeta| {
+            db_meta.verify_table(
+                Direct::FROM,
+                "main".to_string(),
+                "main".to_string(),
+                "orders".to_string(),
+            )
+        };
+        for mode in [VerifyMode::Count, VerifyMode::Aggregates, VerifyMode::Hash] {
+            db_meta.set_verify(mode);
+            assert_eq!(verify(db_meta.clone()).await.unwrap(), Some(vec![]));
+        }
+
+        // a changed value keeps the count and the aggregates of its column
+        db_meta
+            .clone()
+            .execute(
+                Direct::TO,
+                vec!["UPDATE orders SET amount = 3.5 WHERE id = 100".to_string()],
+            )
+            .await
+            .unwrap();
+        db_meta.set_verify(VerifyMode::Aggregates);
+        assert_eq!(verify(db_meta.clone()).await.unwrap(), Some(vec![]));
+        db_meta.set_verify(VerifyMode::Hash);
+        assert_eq!(
+            verify(db_meta.clone()).await.unwrap(),
+            Some(vec!["row hash differs".to_string()])
+        );
+
+        db_meta
+            .clone()
+            .execute(
+                Direct::TO,
+                vec!["DELETE FROM orders WHERE id = 500".to_string()],
+            )
+            .await
+            .unwrap();
+        db_meta.set_verify(VerifyMode::Count);
+        assert_eq!(
+            verify(db_meta.clone()).await.unwrap(),
+            Some(vec!["rows: 500 in source, 499 in target".to_string()])
+        );
+        db_meta.set_verify(VerifyMode::Off);
+        assert_eq!(verify(db_meta).await.unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sqlite_diff() {
+        let (db_meta, dir) = sqlite_meta("diff");
+        seed_orders(&db_meta, Direct::FROM, 2500).await;
+        seed_orders(&db_meta, Direct::TO, 2500).await;
+        let diff = |db_meta: DatabaseMeta, key: &[&str]| {
+            diff::diff_table(
+                db_meta,
+                Direct::FROM,
+                "main".to_string(),
+                "main".to_string(),
+                "orders".to_string(),
+                key.iter().map(|x| x.to_string()).collect(),
+                10,
+            )
+        };
+        assert!(diff(db_meta.clone(), &[]).await.unwrap().is_empty());
+
+        db_meta
+            .clone()
+            .execute(
+                Direct::TO,
+                vec![
+                    "DELETE FROM orders WHERE id = 10".to_string(),
+                    "UPDATE orders SET note = 'changed' WHERE id = 2000".to_string(),
+                    "INSERT INTO orders (id, name) VALUES (9000, 'extra')".to_string(),
+                ],
+            )
+            .await
+            .unwrap();
+        let res = diff(db_meta.clone(), &[]).await.unwrap();
+        assert_eq!((res.only_source, res.only_target, res.differs), (1, 1, 1));
+        let rows = res
+            .rows
+            .iter()
+            .map(|x| (x.kind, res.describe(x)))
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].0, diff::DiffKind::OnlySource);
+        assert_eq!(rows[1].0, diff::DiffKind::Differs);
+        assert!(rows[1].1.contains("changed"), "{}", rows[1].1);
+        assert_eq!(rows[2].0, diff::DiffKind::OnlyTarget);
+
+        // matched by another column, every row of the target differs in key
+        let res = diff(db_meta, &["name"]).await.unwrap();
+        assert_eq!((res.only_source, res.only_target, res.differs), (1, 1, 1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_mode_parse() {
+        assert_eq!(WriteMode::parse(" Upsert "), Some(WriteMode::Upsert));
+        assert_eq!(WriteMode::parse("merge"), Some(WriteMode::Upsert));
+        assert_eq!(WriteMode::parse("ignore"), Some(WriteMode::InsertIgnore));
+        assert_eq!(
+            WriteMode::parse("truncate-then-insert"),
+            Some(WriteMode::Truncate)
+        );
+        assert_eq!(WriteMode::parse("replace"), None);
+        for mode in WriteMode::ALL {
+            assert_eq!(WriteMode::parse(mode.as_str()), Some(mode));
+        }
+    }
+
+    #[test]
+    fn delete_check_parse() {
+        assert_eq!(DeleteCheck::parse("keys"), Some(DeleteCheck::Keys));
+        assert_eq!(DeleteCheck::parse(" KEYS "), Some(DeleteCheck::Keys));
+        assert_eq!(
+            DeleteCheck::parse("flag: IsDeleted"),
+            Some(DeleteCheck::Flag("IsDeleted".to_string()))
+        );
+        assert_eq!(DeleteCheck::parse("flag:"), None);
+        assert_eq!(DeleteCheck::parse("keys:id"), None);
+        assert_eq!(DeleteCheck::parse("rows"), None);
     }
 }
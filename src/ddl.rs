@@ -0,0 +1,289 @@
+use crate::db::{ColumnDetail, Dialect};
+
+/// A column default in a form every dialect can write.
+#[derive(Debug, PartialEq)]
+enum ColumnDefault {
+    Null,
+    Number(String),
+    Bool(bool),
+    Text(String),
+    Now,
+}
+
+/// Reads a default as the source lists it, e.g. `((0))` or `(getdate())`
+/// from MSSQL and `'a'::character varying` from PostgreSQL. `None` when it
+/// is not a literal or the current time.
+fn parse_default(raw: &str) -> Option<ColumnDefault> {
+    let mut s = raw.trim();
+    // MSSQL wraps defaults in parentheses, and so may SQLite
+    while let Some(inner) = s.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+        let mut depth = 0;
+        for c in inner.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return parse_literal(s),
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        s = inner.trim();
+    }
+    parse_literal(s)
+}
+
+fn parse_literal(s: &str) -> Option<ColumnDefault> {
+    if let Some(rest) = s.strip_prefix("N'").or_else(|| s.strip_prefix('\'')) {
+        let mut text = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next()? {
+                '\'' if chars.as_str().starts_with('\'') => {
+                    chars.next();
+                    text.push('\'');
+                }
+                '\'' => break,
+                c => text.push(c),
+            }
+        }
+        // postgres adds a cast, e.g. `'2020-01-01'::date`
+        let rest = chars.as_str();
+        return (rest.is_empty() || rest.starts_with("::")).then_some(ColumnDefault::Text(text));
+    }
+    let s = s.split("::").next().unwrap_or(s).trim();
+    let lower = s.to_lowercase();
+    let now = [
+        "getdate()",
+        "sysdatetime()",
+        "now()",
+        "datetime('now')",
+        "localtimestamp",
+    ];
+    match lower.as_str() {
+        "null" => Some(ColumnDefault::Null),
+        "true" | "false" => Some(ColumnDefault::Bool(lower == "true")),
+        _ if now.contains(&lower.as_str()) => Some(ColumnDefault::Now),
+        // `CURRENT_TIMESTAMP`, or with a precision as mysql lists it
+        _ if lower
+            .strip_prefix("current_timestamp")
+            .map(|x| x.trim_matches(|c: char| c == '(' || c == ')' || c.is_ascii_digit()))
+            == Some("") =>
+        {
+            Some(ColumnDefault::Now)
+        }
+        _ if s.parse::<f64>().is_ok()
+            && s.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) =>
+        {
+            Some(ColumnDefault::Number(s.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// The `DEFAULT` expression of `column` as a `data_type` column in
+/// `dialect`, `Ok(None)` when it has none and `Err` with the source default
+/// when it cannot be carried over.
+pub fn default_clause(
+    dialect: Dialect,
+    column: &ColumnDetail,
+    data_type: &str,
+) -> Result<Option<String>, String> {
+    let raw = match &column.default {
+        // sequences behind identity columns are made by the target itself
+        Some(raw) if !column.identity => raw,
+        _ => return Ok(None),
+    };
+    let t = data_type.to_lowercase();
+    let boolean = dialect == Dialect::Postgres && t.starts_with("bool");
+    Ok(match parse_default(raw) {
+        Some(ColumnDefault::Null) => None,
+        Some(ColumnDefault::Number(x)) if boolean => Some(if x.parse::<f64>() == Ok(0.0) {
+            "FALSE".to_string()
+        } else {
+            "TRUE".to_string()
+        }),
+        Some(ColumnDefault::Number(x)) => Some(x),
+        Some(ColumnDefault::Bool(x)) if boolean => {
+            Some(if x { "TRUE" } else { "FALSE" }.to_string())
+        }
+        Some(ColumnDefault::Bool(x)) => Some(if x { "1" } else { "0" }.to_string()),
+        // mysql allows no literal default on text and blob columns
+        Some(ColumnDefault::Text(_))
+            if dialect == Dialect::Mysql
+                && (t.contains("text") || t.contains("blob") || t == "json") =>
+        {
+            return Err(raw.clone())
+        }
+        Some(ColumnDefault::Text(x)) => Some(dialect.literal(&x)),
+        // mysql only takes the current time on datetime columns, with their precision
+        Some(ColumnDefault::Now) if dialect == Dialect::Mysql => {
+            match t
+                .strip_prefix("datetime")
+                .or_else(|| t.strip_prefix("timestamp"))
+            {
+                Some(precision) => Some(format!("CURRENT_TIMESTAMP{}", precision)),
+                None => return Err(raw.clone()),
+            }
+        }
+        Some(ColumnDefault::Now) => Some("CURRENT_TIMESTAMP".to_string()),
+        None => return Err(raw.clone()),
+    })
+}
+
+fn column_def(dialect: Dialect, column: &ColumnDetail, data_type: &str) -> String {
+    let mut def = format!("{} {}", dialect.quote(&column.name), data_type);
+    if column.identity {
+        match dialect {
+            Dialect::Mssql => def.push_str(" IDENTITY(1,1)"),
+            // mysql only allows auto_increment on a key column
+            Dialect::Mysql if column.primary_key => def.push_str(" AUTO_INCREMENT"),
+            // a lone integer primary key already numbers itself in sqlite
+            Dialect::Mysql | Dialect::Sqlite => {}
+            Dialect::Postgres => def.push_str(" GENERATED BY DEFAULT AS IDENTITY"),
+        }
+    }
+    def.push_str(if column.nullable {
+        " NULL"
+    } else {
+        " NOT NULL"
+    });
+    if let Ok(Some(default)) = default_clause(dialect, column, data_type) {
+        def.push_str(&format!(" DEFAULT {}", default));
+    }
+    if dialect == Dialect::Mysql && !column.comment.is_empty() {
+        def.push_str(&format!(" COMMENT {}", dialect.literal(&column.comment)));
+    }
+    def
+}
+
+/// Statements creating `table` in `ddb` from the source columns paired with
+/// their type in the target dialect.
+pub fn create_table(
+    dialect: Dialect,
+    ddb: &str,
+    table: &str,
+    columns: &[(ColumnDetail, String)],
+) -> Vec<String> {
+    let mut defs = columns
+        .iter()
+        .map(|(column, data_type)| column_def(dialect, column, data_type))
+        .collect::<Vec<String>>();
+
+    let keys = columns
+        .iter()
+        .filter(|(column, _)| column.primary_key)
+        .map(|(column, _)| dialect.quote(&column.name))
+        .collect::<Vec<String>>();
+    if !keys.is_empty() {
+        defs.push(format!("PRIMARY KEY ({})", keys.join(", ")));
+    }
+
+    let mut statements = vec![format!(
+        "CREATE TABLE {} ({})",
+        dialect.table_name(ddb, table),
+        defs.join(", ")
+    )];
+
+    if dialect == Dialect::Postgres {
+        statements.extend(
+            columns
+                .iter()
+                .filter(|(column, _)| !column.comment.is_empty())
+                .map(|(column, _)| {
+                    format!(
+                        "COMMENT ON COLUMN {}.{} IS {}",
+                        dialect.table_name(ddb, table),
+                        dialect.quote(&column.name),
+                        dialect.literal(&column.comment)
+                    )
+                }),
+        );
+    }
+
+    // sql server keeps column comments as extended properties
+    if dialect == Dialect::Mssql {
+        let (schema, name) = dialect.split_table(table);
+        statements.extend(
+            columns
+                .iter()
+                .filter(|(column, _)| !column.comment.is_empty())
+                .map(|(column, _)| {
+                    format!(
+                        "EXEC {}.sys.sp_addextendedproperty @name = N'MS_Description', @value = {}, \
+                         @level0type = N'SCHEMA', @level0name = {}, \
+                         @level1type = N'TABLE', @level1name = {}, \
+                         @level2type = N'COLUMN', @level2name = {}",
+                        dialect.quote(ddb),
+                        dialect.literal(&column.comment),
+                        dialect.literal(schema),
+                        dialect.literal(name),
+                        dialect.literal(&column.name)
+                    )
+                }),
+        );
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_default(default: &str) -> ColumnDetail {
+        ColumnDetail {
+            name: "c".to_string(),
+            default: Some(default.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn translates_defaults() {
+        let clause = |dialect, default, data_type| {
+            default_clause(dialect, &with_default(default), data_type)
+        };
+        let some = |x: &str| Ok(Some(x.to_string()));
+        assert_eq!(clause(Dialect::Mysql, "((0))", "int"), some("0"));
+        assert_eq!(clause(Dialect::Postgres, "((1))", "boolean"), some("TRUE"));
+        assert_eq!(clause(Dialect::Mssql, "false", "bit"), some("0"));
+        assert_eq!(
+            clause(Dialect::Mysql, "(N'it''s')", "varchar(10)"),
+            some("'it''s'")
+        );
+        assert_eq!(
+            clause(Dialect::Mssql, "'2020-01-01'::date", "date"),
+            some("N'2020-01-01'")
+        );
+        assert_eq!(
+            clause(Dialect::Postgres, "(getdate())", "timestamp"),
+            some("CURRENT_TIMESTAMP")
+        );
+        assert_eq!(
+            clause(Dialect::Mysql, "now()", "datetime(3)"),
+            some("CURRENT_TIMESTAMP(3)")
+        );
+        assert_eq!(
+            clause(Dialect::Mssql, "CURRENT_TIMESTAMP(6)", "datetime2"),
+            some("CURRENT_TIMESTAMP")
+        );
+        assert_eq!(
+            clause(Dialect::Sqlite, "NULL::character varying", "text"),
+            Ok(None)
+        );
+        assert_eq!(
+            clause(Dialect::Mysql, "'a'", "longtext"),
+            Err("'a'".to_string())
+        );
+        assert_eq!(
+            clause(Dialect::Mysql, "getdate()", "date"),
+            Err("getdate()".to_string())
+        );
+        assert_eq!(
+            clause(Dialect::Postgres, "(newid())", "uuid"),
+            Err("(newid())".to_string())
+        );
+        assert_eq!(
+            clause(Dialect::Postgres, "NaN", "real"),
+            Err("NaN".to_string())
+        );
+    }
+}
@@ -0,0 +1,839 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::cdc::key_condition;
+use crate::db::{
+    join_all, key_point, ColumnDetail, Compare, DatabaseMeta, Dialect, Direct, KeyKind, KeyRange,
+    BATCH_QUEUE,
+};
+use crate::driver::{Driver, Filter};
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+/// Rows a key range of a compared table should hold. Only ranges whose
+/// hashes differ are read row by row, so this bounds the rows held at once.
+const CHUNK_ROWS: u64 = 10000;
+
+/// Rows looked up on the other side in one query when a table is streamed.
+const LOOKUP_ROWS: usize = 500;
+
+/// Most pieces a differing range is cut into at a time.
+const SPLIT_PARTS: u64 = 16;
+
+/// Key range of a compared table: the column it is over, what the column
+/// holds and the bounds.
+#[derive(Clone)]
+struct Span {
+    column: Arc<String>,
+    kind: KeyKind,
+    range: KeyRange,
+}
+
+impl Span {
+    fn filter(&self, dialect: Dialect) -> Filter {
+        Filter {
+            condition: self.range.condition(dialect, &self.column, self.kind, None),
+            order_by: None,
+        }
+    }
+}
+
+type DiffFuture = Pin<Box<dyn Future<Output = Result<TableDiff>> + Send>>;
+
+/// Where `deleted_keys` sends the keys it finds, a range or batch at a time.
+pub type StaleKeys = mpsc::Sender<Vec<Vec<Value>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffKind {
+    OnlySource,
+    OnlyTarget,
+    Differs,
+}
+
+impl DiffKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiffKind::OnlySource => "only_source",
+            DiffKind::OnlyTarget => "only_target",
+            DiffKind::Differs => "differs",
+        }
+    }
+}
+
+/// A row that is missing on one side or holds other values there.
+#[derive(Debug, Clone)]
+pub struct DiffRow {
+    pub kind: DiffKind,
+    pub source: Option<Vec<Value>>,
+    pub target: Option<Vec<Value>>,
+}
+
+/// How a table differs between source and target.
+#[derive(Debug, Clone, Default)]
+pub struct TableDiff {
+    pub columns: Vec<String>,
+    /// positions of the columns rows are matched by
+    pub key: Vec<usize>,
+    /// how the values of every column are compared and shown
+    pub(crate) compare: Vec<Compare>,
+    pub only_source: u64,
+    pub only_target: u64,
+    pub differs: u64,
+    /// the first differing rows in key order, up to the limit asked for
+    pub rows: Vec<DiffRow>,
+}
+
+impl TableDiff {
+    /// Adds the counts and rows of `part`, whose rows follow these in key order.
+    fn merge(&mut self, part: TableDiff) {
+        self.only_source += part.only_source;
+        self.only_target += part.only_target;
+        self.differs += part.differs;
+        self.rows.extend(part.rows);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.only_source == 0 && self.only_target == 0 && self.differs == 0
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} only in source, {} only in target, {} differ",
+            self.only_source, self.only_target, self.differs
+        )
+    }
+
+    /// One line naming the row by its key, with the changed columns of rows
+    /// that differ, e.g. `differs id=7: amount 1.25 -> 1`.
+    /// `value` of column `idx` as it is compared.
+    fn text(&self, idx: usize, value: &Value) -> Option<String> {
+        match self.compare.get(idx) {
+            Some(compare) => compare.text(value),
+            None => value.canonical(),
+        }
+    }
+
+    pub fn describe(&self, row: &DiffRow) -> String {
+        let show = |idx: usize, x: &Value| self.text(idx, x).unwrap_or_else(|| "null".to_string());
+        let values = match (&row.source, &row.target) {
+            (Some(values), _) | (None, Some(values)) => values,
+            (None, None) => return String::new(),
+        };
+        let key = self
+            .key
+            .iter()
+            .map(|&idx| format!("{}={}", self.columns[idx], show(idx, &values[idx])))
+            .collect::<Vec<String>>()
+            .join(", ");
+        match (row.kind, &row.source, &row.target) {
+            (DiffKind::Differs, Some(source), Some(target)) => {
+                let changes = self
+                    .columns
+                    .iter()
+                    .zip(source.iter().zip(target))
+                    .enumerate()
+                    .filter(|(idx, (_, (a, b)))| self.text(*idx, a) != self.text(*idx, b))
+                    .map(|(idx, (name, (a, b)))| {
+                        format!("{} {} -> {}", name, show(idx, a), show(idx, b))
+                    })
+                    .collect::<Vec<String>>();
+                format!("differs {}: {}", key, changes.join("; "))
+            }
+            (DiffKind::OnlyTarget, ..) => format!("only in target {}", key),
+            _ => format!("only in source {}", key),
+        }
+    }
+
+    /// Writes the differing rows to `path` as CSV: the kind of difference,
+    /// the side the values were read from and then every column. Rows that
+    /// differ take one line per side; null is an empty field.
+    pub fn write_csv(&self, path: &Path) -> Result<()> {
+        let mut out = String::new();
+        let header = ["diff", "side"]
+            .iter()
+            .map(|x| Some(x.to_string()))
+            .chain(self.columns.iter().map(|x| Some(x.clone())));
+        push_csv_line(&mut out, header);
+        for row in &self.rows {
+            for (side, values) in [("source", &row.source), ("target", &row.target)] {
+                if let Some(values) = values {
+                    let line = [Some(row.kind.as_str().to_string()), Some(side.to_string())]
+                        .into_iter()
+                        .chain(values.iter().enumerate().map(|(idx, x)| self.text(idx, x)));
+                    push_csv_line(&mut out, line);
+                }
+            }
+        }
+        std::fs::write(path, out)
+            .map_err(|e| Error::Query(format!("{}: {}", path.display(), e).into()))
+    }
+}
+
+fn push_csv_line(out: &mut String, fields: impl Iterator<Item = Option<String>>) {
+    let fields = fields
+        .map(|field| match field {
+            None => String::new(),
+            Some(x) if x.is_empty() || x.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", x.replace('"', "\"\""))
+            }
+            Some(x) => x,
+        })
+        .collect::<Vec<String>>();
+    out.push_str(&fields.join(","));
+    out.push_str("\r\n");
+}
+
+/// Orders keys numerically where both sides are numbers.
+fn compare_keys(a: &[Option<String>], b: &[Option<String>]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let number = |x: &Option<String>| x.as_deref().and_then(|x| x.parse::<f64>().ok());
+        let ord = match (number(a), number(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            _ => a.cmp(b),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Compares `table` on the `direct` side with the same table on the other
+/// side, matching rows by the `key` columns or else the primary key, and
+/// keeps the first `limit` differing rows. The table is split into key
+/// ranges that are hashed on both sides first, so only the ranges that
+/// differ are read row by row.
+pub async fn diff_table(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    table: String,
+    key: Vec<String>,
+    limit: usize,
+) -> Result<TableDiff> {
+    let detail = db_meta
+        .clone()
+        .table_detail(direct, from_ddb.clone(), table.clone())
+        .await?;
+    if detail.is_empty() {
+        return Err(Error::Query("table not found or has no columns".into()));
+    }
+    let columns = detail
+        .iter()
+        .map(|x| x.name.clone())
+        .collect::<Vec<String>>();
+    let key = if key.is_empty() {
+        detail
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.primary_key)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<usize>>()
+    } else {
+        key.iter()
+            .map(|name| {
+                columns
+                    .iter()
+                    .position(|x| x.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| Error::Query(format!("no column {}", name).into()))
+            })
+            .collect::<Result<Vec<usize>>>()?
+    };
+    if key.is_empty() {
+        return Err(Error::Query("no primary key to match rows by".into()));
+    }
+    compare(
+        db_meta, direct, from_ddb, to_ddb, table, detail, key, limit, None,
+    )
+    .await
+}
+
+/// Sends `tx` the keys of the rows of `table` on the other side of `direct`
+/// that are gone from the `direct` side as each range turns them up, found
+/// like `diff_table` but over the `key` columns alone.
+pub async fn deleted_keys(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    table: String,
+    key: Vec<String>,
+    tx: StaleKeys,
+) -> Result<()> {
+    let columns = db_meta
+        .clone()
+        .table_detail(direct, from_ddb.clone(), table.clone())
+        .await?;
+    // in the order of `key`, which the values sent follow
+    let detail = key
+        .iter()
+        .map(|name| {
+            columns
+                .iter()
+                .find(|x| x.name.eq_ignore_ascii_case(name))
+                .cloned()
+                .ok_or_else(|| Error::Query(format!("no column {}", name).into()))
+        })
+        .collect::<Result<Vec<ColumnDetail>>>()?;
+    let key = (0..detail.len()).collect();
+    compare(
+        db_meta,
+        direct,
+        from_ddb,
+        to_ddb,
+        table,
+        detail,
+        key,
+        0,
+        Some(tx),
+    )
+    .await?;
+    Ok(())
+}
+
+/// `diff_table` over the given columns, matching rows by the `key` positions.
+#[allow(clippy::too_many_arguments)]
+async fn compare(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    table: String,
+    mut detail: Vec<ColumnDetail>,
+    key: Vec<usize>,
+    limit: usize,
+    stale: Option<StaleKeys>,
+) -> Result<TableDiff> {
+    let columns = detail
+        .iter()
+        .map(|x| x.name.clone())
+        .collect::<Vec<String>>();
+    let other = db_meta
+        .clone()
+        .table_detail(direct.toggle(), to_ddb.clone(), table.clone())
+        .await?;
+    let compare = Arc::new(Compare::columns(&detail, &other));
+    // ranges are planned over the first primary key column
+    for (idx, column) in detail.iter_mut().enumerate() {
+        column.primary_key = idx == key[0];
+    }
+
+    let from = db_meta.dialect(direct);
+    let count = db_meta
+        .clone()
+        .query(
+            direct,
+            format!(
+                "SELECT {} FROM {}",
+                from.count("*"),
+                from.table_name(&from_ddb, &table)
+            ),
+        )
+        .await?;
+    let rows = count
+        .first()
+        .and_then(|x| x.first())
+        .and_then(Value::canonical)
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(0);
+    let mut planner = db_meta.clone();
+    planner.set_chunks((rows / CHUNK_ROWS + 1) as usize, None);
+    let progress = planner.plan(direct, &from_ddb, &table, &detail).await?;
+
+    let columns = Arc::new(columns);
+    let key = Arc::new(key);
+    if progress.key.is_none() && rows > CHUNK_ROWS {
+        return compare_streamed(
+            db_meta, direct, from_ddb, to_ddb, table, columns, compare, key, limit, stale,
+        )
+        .await;
+    }
+    let semaphore = Arc::new(Semaphore::new(db_meta.workers()));
+    let column = progress.key.map(Arc::new);
+    let tasks = progress
+        .chunks
+        .iter()
+        .map(|chunk| {
+            let span = column.as_ref().map(|column| Span {
+                column: column.clone(),
+                kind: progress.kind,
+                range: chunk.range,
+            });
+            let db_meta = db_meta.clone();
+            let semaphore = semaphore.clone();
+            let (from_ddb, to_ddb, table) = (from_ddb.clone(), to_ddb.clone(), table.clone());
+            let (columns, compare, key) = (columns.clone(), compare.clone(), key.clone());
+            let stale = stale.clone();
+            async move {
+                let _permit = semaphore.acquire().await?;
+                diff_chunk(
+                    db_meta, direct, from_ddb, to_ddb, table, columns, compare, key, span, limit,
+                    stale,
+                )
+                .await
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut diff = TableDiff {
+        columns: columns.to_vec(),
+        key: key.to_vec(),
+        compare: compare.to_vec(),
+        ..Default::default()
+    };
+    // chunks come in key order, each with its rows sorted
+    for part in join_all(tasks).await? {
+        diff.merge(part);
+    }
+    diff.rows.truncate(limit);
+    Ok(diff)
+}
+
+/// Differences within the rows of `span`, or of the whole table without one,
+/// without reading them when both sides hash the same. A differing range
+/// holding more rows than a chunk should is cut at the keys its rows span
+/// and each piece compared in turn, so skewed keys are not read at once.
+#[allow(clippy::too_many_arguments)]
+fn diff_chunk(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    table: String,
+    columns: Arc<Vec<String>>,
+    compare: Arc<Vec<Compare>>,
+    key: Arc<Vec<usize>>,
+    span: Option<Span>,
+    limit: usize,
+    stale: Option<StaleKeys>,
+) -> DiffFuture {
+    Box::pin(async move {
+        let target_side = direct.toggle();
+        let (from, to) = (db_meta.dialect(direct), db_meta.dialect(target_side));
+        let (source, target) = match &span {
+            Some(span) => (span.filter(from), span.filter(to)),
+            None => (Filter::default(), Filter::default()),
+        };
+        let (a, b) = tokio::try_join!(
+            db_meta.clone().row_hash(
+                direct,
+                from_ddb.clone(),
+                table.clone(),
+                columns.clone(),
+                compare.clone(),
+                source.clone()
+            ),
+            db_meta.clone().row_hash(
+                target_side,
+                to_ddb.clone(),
+                table.clone(),
+                columns.clone(),
+                compare.clone(),
+                target.clone()
+            ),
+        )?;
+        let mut diff = TableDiff::default();
+        if a == b {
+            return Ok(diff);
+        }
+        if let Some(span) = &span {
+            let parts = split(
+                db_meta.clone(),
+                direct,
+                &from_ddb,
+                &to_ddb,
+                &table,
+                span,
+                (&source, &target),
+            )
+            .await?;
+            if !parts.is_empty() {
+                for part in parts {
+                    let part = diff_chunk(
+                        db_meta.clone(),
+                        direct,
+                        from_ddb.clone(),
+                        to_ddb.clone(),
+                        table.clone(),
+                        columns.clone(),
+                        compare.clone(),
+                        key.clone(),
+                        Some(part),
+                        limit,
+                        stale.clone(),
+                    )
+                    .await?;
+                    diff.merge(part);
+                    diff.rows.truncate(limit);
+                }
+                return Ok(diff);
+            }
+        }
+
+        let (source, target) = tokio::try_join!(
+            fetch(
+                db_meta.clone(),
+                direct,
+                &from_ddb,
+                &table,
+                &columns,
+                &source
+            ),
+            fetch(
+                db_meta.clone(),
+                target_side,
+                &to_ddb,
+                &table,
+                &columns,
+                &target
+            ),
+        )?;
+        let key_of = |row: &[Value]| {
+            key.iter()
+                .map(|&idx| compare[idx].text(&row[idx]))
+                .collect::<Vec<Option<String>>>()
+        };
+        let mut by_key = HashMap::with_capacity(source.len());
+        for row in source {
+            let k = key_of(&row);
+            if by_key.contains_key(&k) {
+                return Err(Error::Query(
+                    format!("more than one source row with key {:?}", k).into(),
+                ));
+            }
+            by_key.insert(k, row);
+        }
+        let mut rows = vec![];
+        for row in target {
+            let k = key_of(&row);
+            match by_key.remove(&k) {
+                None => {
+                    diff.only_target += 1;
+                    rows.push((
+                        k,
+                        DiffRow {
+                            kind: DiffKind::OnlyTarget,
+                            source: None,
+                            target: Some(row),
+                        },
+                    ));
+                }
+                Some(other) => {
+                    if other
+                        .iter()
+                        .zip(&row)
+                        .zip(compare.iter())
+                        .any(|((a, b), compare)| compare.text(a) != compare.text(b))
+                    {
+                        diff.differs += 1;
+                        rows.push((
+                            k,
+                            DiffRow {
+                                kind: DiffKind::Differs,
+                                source: Some(other),
+                                target: Some(row),
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        diff.only_source = by_key.len() as u64;
+        rows.extend(by_key.into_iter().map(|(k, row)| {
+            (
+                k,
+                DiffRow {
+                    kind: DiffKind::OnlySource,
+                    source: Some(row),
+                    target: None,
+                },
+            )
+        }));
+        send_stale(&stale, &mut rows).await?;
+        rows.sort_by(|a, b| compare_keys(&a.0, &b.0));
+        rows.truncate(limit);
+        diff.rows = rows.into_iter().map(|x| x.1).collect();
+        Ok(diff)
+    })
+}
+
+/// Hands the rows only in the target to `stale`, when given, instead of
+/// keeping any of `rows`.
+async fn send_stale(
+    stale: &Option<StaleKeys>,
+    rows: &mut Vec<(Vec<Option<String>>, DiffRow)>,
+) -> Result<()> {
+    if let Some(tx) = stale {
+        let keys = rows
+            .drain(..)
+            .filter(|x| x.1.kind == DiffKind::OnlyTarget)
+            .filter_map(|x| x.1.target)
+            .collect::<Vec<Vec<Value>>>();
+        if !keys.is_empty() {
+            tx.send(keys).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Pieces to cut `span` into when its rows on both sides are more than a
+/// chunk should hold, or none when they fit or all share one key value.
+async fn split(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: &str,
+    to_ddb: &str,
+    table: &str,
+    span: &Span,
+    (source, target): (&Filter, &Filter),
+) -> Result<Vec<Span>> {
+    let low_high = match span.range {
+        KeyRange::Null => return Ok(vec![]),
+        KeyRange::Between(low, high) => (low, high),
+    };
+    let (a, b) = tokio::try_join!(
+        key_bounds(
+            db_meta.clone(),
+            direct,
+            from_ddb,
+            table,
+            &span.column,
+            source
+        ),
+        key_bounds(
+            db_meta.clone(),
+            direct.toggle(),
+            to_ddb,
+            table,
+            &span.column,
+            target
+        ),
+    )?;
+    let rows = a.0 + b.0;
+    let low = a.1.into_iter().chain(b.1).min();
+    let high = a.2.into_iter().chain(b.2).max();
+    let (low, high) = match (low, high) {
+        (Some(low), Some(high)) if rows > CHUNK_ROWS * 2 && high > low => (low, high + 1),
+        _ => return Ok(vec![]),
+    };
+    // never wider than the range itself
+    let low = low_high.0.map_or(low, |x| low.max(x));
+    let high = low_high.1.map_or(high, |x| high.min(x));
+    let parts = (rows / (CHUNK_ROWS * 2) + 1).clamp(2, SPLIT_PARTS) as i128;
+    let step = ((high as i128 - low as i128 + parts - 1) / parts) as i64;
+    let mut spans = vec![];
+    let mut start = low;
+    while start < high {
+        let end = start.saturating_add(step).min(high);
+        spans.push(Span {
+            range: KeyRange::Between(Some(start), Some(end)),
+            ..span.clone()
+        });
+        start = end;
+    }
+    Ok(spans)
+}
+
+/// Rows `filter` selects on the `direct` side, with the lowest and highest
+/// value of `column` among them.
+async fn key_bounds(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    ddb: &str,
+    table: &str,
+    column: &str,
+    filter: &Filter,
+) -> Result<(u64, Option<i64>, Option<i64>)> {
+    let dialect = db_meta.dialect(direct);
+    let quoted = dialect.quote(column);
+    let res = db_meta
+        .query(
+            direct,
+            format!(
+                "SELECT {}, MIN({}), MAX({}) FROM {}{}",
+                dialect.count("*"),
+                quoted,
+                quoted,
+                dialect.table_name(ddb, table),
+                filter.clause()
+            ),
+        )
+        .await?;
+    Ok(match res.first().map(|x| x.as_slice()) {
+        Some([count, low, high]) => (
+            count
+                .canonical()
+                .and_then(|x| x.parse::<u64>().ok())
+                .unwrap_or(0),
+            key_point(low).map(|x| x.1),
+            key_point(high).map(|x| x.1),
+        ),
+        _ => (0, None, None),
+    })
+}
+
+/// `compare` for a table too large to read at once whose key cannot be split
+/// into ranges, such as a text or GUID key. Each side is streamed in batches
+/// whose rows are looked up on the other side by key, so only a batch and
+/// the differing rows kept are held at a time.
+#[allow(clippy::too_many_arguments)]
+async fn compare_streamed(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    from_ddb: String,
+    to_ddb: String,
+    table: String,
+    columns: Arc<Vec<String>>,
+    compare: Arc<Vec<Compare>>,
+    key: Arc<Vec<usize>>,
+    limit: usize,
+    stale: Option<StaleKeys>,
+) -> Result<TableDiff> {
+    let key_of = |row: &[Value]| {
+        key.iter()
+            .map(|&idx| compare[idx].text(&row[idx]))
+            .collect::<Vec<Option<String>>>()
+    };
+    let differs = |a: &[Value], b: &[Value]| {
+        a.iter()
+            .zip(b)
+            .zip(compare.iter())
+            .any(|((a, b), compare)| compare.text(a) != compare.text(b))
+    };
+    let names = key
+        .iter()
+        .map(|&idx| columns[idx].clone())
+        .collect::<Vec<String>>();
+    let batch_size = db_meta.batch_size(&table);
+    let mut diff = TableDiff {
+        columns: columns.to_vec(),
+        key: key.to_vec(),
+        compare: compare.to_vec(),
+        ..Default::default()
+    };
+    let mut rows = vec![];
+    // rows only in the source or differing first, then rows only in the target
+    for forward in [true, false] {
+        let (side, ddb, other_ddb) = match forward {
+            true => (direct, &from_ddb, &to_ddb),
+            false => (direct.toggle(), &to_ddb, &from_ddb),
+        };
+        let dialect = db_meta.dialect(side.toggle());
+        let mut outer = db_meta.clone().client(side).await?;
+        let mut inner = db_meta.clone().client(side.toggle()).await?;
+        let (tx, mut rx) = mpsc::channel(BATCH_QUEUE);
+        let all = Filter::default();
+        let read = outer.read_rows(ddb, &table, &columns, &all, batch_size, tx);
+        let matched = async {
+            while let Some(batch) = rx.recv().await {
+                for part in batch.chunks(LOOKUP_ROWS) {
+                    let keys = part
+                        .iter()
+                        .map(|row| key.iter().map(|&idx| row[idx].clone()).collect())
+                        .collect::<Vec<Vec<Value>>>();
+                    let filter = Filter {
+                        condition: Some(key_condition(dialect, &names, &keys)),
+                        order_by: None,
+                    };
+                    let found = read_all(
+                        inner.as_mut(),
+                        other_ddb,
+                        &table,
+                        &columns,
+                        &filter,
+                        batch_size,
+                    )
+                    .await?;
+                    let mut by_key = found
+                        .into_iter()
+                        .map(|row| (key_of(&row), row))
+                        .collect::<HashMap<_, _>>();
+                    for row in part {
+                        let k = key_of(row);
+                        let (kind, source, target) = match by_key.remove(&k) {
+                            None if forward => {
+                                diff.only_source += 1;
+                                (DiffKind::OnlySource, Some(row.clone()), None)
+                            }
+                            None => {
+                                diff.only_target += 1;
+                                (DiffKind::OnlyTarget, None, Some(row.clone()))
+                            }
+                            Some(other) if forward && differs(row, &other) => {
+                                diff.differs += 1;
+                                (DiffKind::Differs, Some(row.clone()), Some(other))
+                            }
+                            Some(_) => continue,
+                        };
+                        rows.push((
+                            k,
+                            DiffRow {
+                                kind,
+                                source,
+                                target,
+                            },
+                        ));
+                    }
+                    send_stale(&stale, &mut rows).await?;
+                    if rows.len() > limit.saturating_mul(2) {
+                        rows.sort_by(|a, b| compare_keys(&a.0, &b.0));
+                        rows.truncate(limit);
+                    }
+                }
+            }
+            Ok(())
+        };
+        let res = tokio::try_join!(read, matched);
+        outer.close().await?;
+        inner.close().await?;
+        res?;
+    }
+    rows.sort_by(|a, b| compare_keys(&a.0, &b.0));
+    rows.truncate(limit);
+    diff.rows = rows.into_iter().map(|x| x.1).collect();
+    Ok(diff)
+}
+
+/// Every row of `table` on the `direct` side that `filter` selects.
+async fn fetch(
+    db_meta: DatabaseMeta,
+    direct: Direct,
+    ddb: &str,
+    table: &str,
+    columns: &[String],
+    filter: &Filter,
+) -> Result<Vec<Vec<Value>>> {
+    let batch_size = db_meta.batch_size(table);
+    let mut client = db_meta.client(direct).await?;
+    let res = read_all(client.as_mut(), ddb, table, columns, filter, batch_size).await;
+    client.close().await?;
+    res
+}
+
+/// `fetch` over a connection already open.
+async fn read_all(
+    client: &mut dyn Driver,
+    ddb: &str,
+    table: &str,
+    columns: &[String],
+    filter: &Filter,
+    batch_size: usize,
+) -> Result<Vec<Vec<Value>>> {
+    let (tx, mut rx) = mpsc::channel(BATCH_QUEUE);
+    let read = client.read_rows(ddb, table, columns, filter, batch_size, tx);
+    let collect = async {
+        let mut rows = vec![];
+        while let Some(batch) = rx.recv().await {
+            rows.extend(batch);
+        }
+        Ok(rows)
+    };
+    tokio::try_join!(read, collect).map(|(_, rows)| rows)
+}
@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+
+use crate::db::{ColumnDetail, DatabaseConfig};
+use crate::error::Result;
+use crate::value::Value;
+
+pub mod mssql;
+pub mod mysql;
+pub mod postgres;
+pub mod sqlite;
+
+/// Whether `execute` echoes its statements, set by `--verbose`.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+/// Prints a statement about to run when `--verbose` is on.
+pub(crate) fn log_statement(sql_str: &str) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        eprintln!("{}", sql_str);
+    }
+}
+
+/// Rows handed from a reader to a writer in one piece.
+pub type Batch = Vec<Vec<Value>>;
+
+/// One open connection to a database backend. `DatabaseMeta` and the
+/// transfer flow only talk to backends through this trait, so a new backend
+/// needs an implementation here and an arm in `connect`.
+#[async_trait]
+pub trait Driver: Send {
+    async fn show_dbs(&mut self) -> Result<Vec<String>>;
+
+    async fn show_tables(&mut self, ddb: &str) -> Result<Vec<String>>;
+
+    async fn table_exists(&mut self, ddb: &str, table: &str) -> Result<bool>;
+
+    async fn table_detail(&mut self, ddb: &str, table: &str) -> Result<Vec<ColumnDetail>>;
+
+    /// Streams `columns` of the rows of `table` selected by `filter` into
+    /// `tx` in batches of at most `batch_size` rows, returning the number of
+    /// rows read. Only one batch is held at a time, so memory stays bounded
+    /// whatever the table size.
+    async fn read_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        filter: &Filter,
+        batch_size: usize,
+        tx: Sender<Batch>,
+    ) -> Result<u64>;
+
+    /// Inserts `rows` into `table`, returning the number of rows written, or
+    /// sent when rows already in the table are updated or skipped.
+    async fn write_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64>;
+
+    /// Every row of a query returning a small result, such as an aggregate.
+    async fn query(&mut self, sql_str: &str) -> Result<Vec<Vec<Value>>>;
+
+    async fn execute(&mut self, statements: &[String]) -> Result<()>;
+
+    async fn close(self: Box<Self>) -> Result<()>;
+}
+
+/// What `write_rows` does with a row whose key is already in the table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OnConflict {
+    /// insert it anyway, failing on a unique key
+    #[default]
+    Fail,
+    /// overwrite the existing row, matched by these key columns
+    Update(Vec<String>),
+    /// keep the existing row, matched by these key columns
+    Skip(Vec<String>),
+}
+
+impl OnConflict {
+    pub fn key(&self) -> &[String] {
+        match self {
+            OnConflict::Fail => &[],
+            OnConflict::Update(key) | OnConflict::Skip(key) => key,
+        }
+    }
+
+    /// Columns an update overwrites: all but the key.
+    pub fn updated<'a>(&self, columns: &'a [String]) -> Vec<&'a String> {
+        match self {
+            OnConflict::Update(key) => columns
+                .iter()
+                .filter(|x| !key.iter().any(|k| k.eq_ignore_ascii_case(x)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Which rows `read_rows` reads and in what order, as SQL in the source
+/// dialect.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub condition: Option<String>,
+    pub order_by: Option<String>,
+}
+
+impl Filter {
+    /// ` WHERE ... ORDER BY ...`, leaving out the parts that are not set.
+    pub(crate) fn clause(&self) -> String {
+        let mut res = String::new();
+        if let Some(condition) = &self.condition {
+            res.push_str(&format!(" WHERE {}", condition));
+        }
+        if let Some(order_by) = &self.order_by {
+            res.push_str(&format!(" ORDER BY {}", order_by));
+        }
+        res
+    }
+}
+
+/// Why an operation failed, as far as retrying it is concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorClass {
+    /// the connection dropped or could not be made
+    Network,
+    Deadlock,
+    LockTimeout,
+    /// bad credentials, syntax, data and everything else
+    Permanent,
+}
+
+impl ErrorClass {
+    /// Whether the same operation may succeed when tried again.
+    pub fn is_transient(self) -> bool {
+        self != ErrorClass::Permanent
+    }
+}
+
+/// Classifies `e` by the first error in its source chain that a backend
+/// recognizes.
+pub fn classify(e: &(dyn std::error::Error + 'static)) -> ErrorClass {
+    let backends = [
+        io_error_class,
+        mssql::error_class,
+        mysql::error_class,
+        postgres::error_class,
+        sqlite::error_class,
+    ];
+    let mut cur = Some(e);
+    while let Some(e) = cur {
+        if let Some(class) = backends.iter().find_map(|f| f(e)) {
+            return class;
+        }
+        cur = e.source();
+    }
+    ErrorClass::Permanent
+}
+
+fn io_error_class(e: &(dyn std::error::Error + 'static)) -> Option<ErrorClass> {
+    use std::io::ErrorKind;
+    let e = e.downcast_ref::<std::io::Error>()?;
+    match e.kind() {
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe
+        | ErrorKind::TimedOut
+        | ErrorKind::UnexpectedEof
+        | ErrorKind::Interrupted => Some(ErrorClass::Network),
+        _ => None,
+    }
+}
+
+pub async fn connect(db: &DatabaseConfig) -> Result<Box<dyn Driver>> {
+    match db.class.as_str() {
+        "MSSQL" => Ok(Box::new(mssql::MssqlDriver::connect(db).await?)),
+        "POSTGRES" => Ok(Box::new(postgres::PostgresDriver::connect(db).await?)),
+        "SQLITE" => Ok(Box::new(sqlite::SqliteDriver::connect(db).await?)),
+        _ => Ok(Box::new(mysql::MysqlDriver::connect(db).await?)),
+    }
+}
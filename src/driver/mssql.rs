@@ -0,0 +1,853 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use futures::TryStreamExt;
+use tiberius::numeric::Numeric;
+use tiberius::time::{
+    Date, DateTime as TdsDateTime, DateTime2, DateTimeOffset, SmallDateTime, Time,
+};
+use tiberius::{AuthMethod, Client, ColumnData, Config, ToSql, TokenRow, Uuid};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+
+use super::{log_statement, Batch, Driver, ErrorClass, Filter, OnConflict};
+use crate::db::{ColumnDetail, DatabaseConfig, Dialect};
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+/// Temp table used to bulk load tables with an identity column, or rows to
+/// merge into the table.
+const STAGE: &str = "#sql_transfer_stage";
+
+pub struct MssqlDriver {
+    client: Client<Compat<TcpStream>>,
+    /// columns of the tables written so far, by full name
+    targets: HashMap<String, Vec<ColumnDetail>>,
+    /// table the stage currently has the columns of
+    staged: Option<String>,
+}
+
+pub(super) fn error_class(e: &(dyn std::error::Error + 'static)) -> Option<ErrorClass> {
+    match e.downcast_ref::<tiberius::error::Error>()? {
+        tiberius::error::Error::Io { .. } => Some(ErrorClass::Network),
+        e => match e.code()? {
+            1205 => Some(ErrorClass::Deadlock),
+            1222 => Some(ErrorClass::LockTimeout),
+            // connection closed by the server, or Azure SQL busy or failing over
+            233 | 10053 | 10054 | 40197 | 40501 | 40613 => Some(ErrorClass::Network),
+            _ => Some(ErrorClass::Permanent),
+        },
+    }
+}
+
+impl MssqlDriver {
+    pub async fn connect(db: &DatabaseConfig) -> Result<Self> {
+        let mut config = Config::new();
+        config.host(&db.host);
+        config.port(db.port);
+        config.authentication(AuthMethod::sql_server(&db.user, &db.password));
+        config.trust_cert();
+
+        let tcp = TcpStream::connect(config.get_addr()).await?;
+        //tcp.set_nodelay(true)?;
+
+        let client = Client::connect(config, tcp.compat_write()).await?;
+        Ok(Self {
+            client,
+            targets: HashMap::new(),
+            staged: None,
+        })
+    }
+
+    /// Streams the rows of any query into `tx` like `read_rows`, such as the
+    /// changes a capture instance returns.
+    pub async fn read_query(
+        &mut self,
+        sql_str: String,
+        batch_size: usize,
+        tx: Sender<Batch>,
+    ) -> Result<u64> {
+        let mut stream = self.client.simple_query(sql_str).await?.into_row_stream();
+        let mut read = 0;
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(row) = stream.try_next().await? {
+            batch.push(row.into_iter().map(Value::from).collect::<Vec<Value>>());
+            if batch.len() >= batch_size {
+                read += batch.len() as u64;
+                tx.send(std::mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(batch_size),
+                ))
+                .await?;
+            }
+        }
+        if !batch.is_empty() {
+            read += batch.len() as u64;
+            tx.send(batch).await?;
+        }
+        Ok(read)
+    }
+
+    async fn insert_rows(
+        &mut self,
+        table_name: &str,
+        target: &[ColumnDetail],
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        let column_list = columns
+            .iter()
+            .map(|x| Dialect::Mssql.quote(x))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let params = (1..=columns.len())
+            .map(|i| format!("@P{}", i))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let sql_str = match on_conflict {
+            OnConflict::Fail => format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                table_name, column_list, params
+            ),
+            _ => merge_sql(
+                table_name,
+                &format!("(VALUES ({})) AS s ({})", params, column_list),
+                target,
+                columns,
+                on_conflict,
+            ),
+        };
+        // NULL sent as the type of its column, which an untyped NULL may
+        // not convert to
+        let nulls = columns
+            .iter()
+            .map(|name| {
+                let column = target.iter().find(|x| x.name.eq_ignore_ascii_case(name));
+                TypedNull(column.map_or(ColumnData::String(None), null_data))
+            })
+            .collect::<Vec<TypedNull>>();
+        // keep the source values of identity columns; only one table of a
+        // session may have IDENTITY_INSERT on, so it is turned off again
+        let identity = columns.iter().any(|name| {
+            target
+                .iter()
+                .any(|x| x.identity && x.name.eq_ignore_ascii_case(name))
+        });
+        let (begin, identity_off) = if identity {
+            (
+                format!("BEGIN TRANSACTION; SET IDENTITY_INSERT {} ON", table_name),
+                format!("SET IDENTITY_INSERT {} OFF; ", table_name),
+            )
+        } else {
+            ("BEGIN TRANSACTION".to_string(), String::new())
+        };
+
+        // one transaction, so a batch failing halfway leaves no rows behind
+        // to be written twice when it is retried
+        let mut written = 0;
+        let mut res = match self.client.simple_query(begin).await {
+            Ok(stream) => stream.into_results().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if res.is_ok() {
+            for row in rows {
+                let params = row
+                    .iter()
+                    .zip(&nulls)
+                    .map(|(x, null)| match x {
+                        Value::Null => null as &dyn ToSql,
+                        x => x as &dyn ToSql,
+                    })
+                    .collect::<Vec<&dyn ToSql>>();
+                if let Err(e) = self.client.execute(sql_str.as_str(), &params).await {
+                    res = Err(e);
+                    break;
+                }
+                written += 1;
+            }
+        }
+        let end = match res {
+            Ok(()) => format!("{}COMMIT TRANSACTION", identity_off),
+            Err(_) => format!("IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION; {}", identity_off),
+        };
+        let ended = match self.client.simple_query(end).await {
+            Ok(stream) => stream.into_results().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        res?;
+        ended?;
+        Ok(written)
+    }
+
+    /// Bulk loads straight into the table. The server expects every
+    /// insertable column in table order, so columns missing from the source
+    /// are sent as NULL.
+    async fn bulk_direct(
+        &mut self,
+        table_name: &str,
+        target: &[ColumnDetail],
+        written: &[&ColumnDetail],
+        rows: Vec<Vec<Value>>,
+    ) -> Result<u64> {
+        let order = target
+            .iter()
+            .filter(|x| !x.identity && !x.computed && x.data_type != "timestamp")
+            .map(|x| {
+                (
+                    x,
+                    written
+                        .iter()
+                        .position(|w| w.name.eq_ignore_ascii_case(&x.name)),
+                )
+            })
+            .collect::<Vec<(&ColumnDetail, Option<usize>)>>();
+
+        let mut req = self.client.bulk_insert(table_name).await?;
+        for row in rows {
+            let mut row = row.into_iter().map(Some).collect::<Vec<Option<Value>>>();
+            let mut data = TokenRow::with_capacity(order.len());
+            for (column, idx) in &order {
+                let value = idx.and_then(|i| row[i].take()).unwrap_or(Value::Null);
+                data.push(bulk_data(value, column)?);
+            }
+            req.send(data).await?;
+        }
+        Ok(req.finalize().await?.total())
+    }
+
+    /// Bulk loads into a temp table without the identity property, then
+    /// copies or merges into the table with `IDENTITY_INSERT` on so the
+    /// source identity values are kept. Columns of types a bulk load cannot
+    /// encode are staged as a type that converts to them.
+    async fn bulk_staged(
+        &mut self,
+        table_name: &str,
+        target: &[ColumnDetail],
+        written: &[&ColumnDetail],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        let names = written
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<String>>();
+        let columns = names
+            .iter()
+            .map(|x| Dialect::Mssql.quote(x))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let loaded = written
+            .iter()
+            .map(|x| stage_column(x).unwrap_or_else(|| (*x).clone()))
+            .collect::<Vec<ColumnDetail>>();
+        if self.staged.as_deref() != Some(table_name) {
+            let exprs = written
+                .iter()
+                .zip(&loaded)
+                .map(|(column, stage)| {
+                    let name = Dialect::Mssql.quote(&column.name);
+                    if column.data_type == stage.data_type {
+                        name
+                    } else {
+                        format!("CAST({} AS {}) AS {}", name, stage.column_type, name)
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            // a union drops the identity property from the copied columns
+            self.client
+                .simple_query(format!(
+                    "IF OBJECT_ID('tempdb..{stage}') IS NOT NULL DROP TABLE {stage}; \
+                     SELECT TOP 0 {exprs} INTO {stage} FROM {table} \
+                     UNION ALL SELECT TOP 0 {exprs} FROM {table}",
+                    stage = STAGE,
+                    exprs = exprs,
+                    table = table_name
+                ))
+                .await?
+                .into_results()
+                .await?;
+            self.staged = Some(table_name.to_string());
+        } else {
+            // rows left by a batch that failed before it was copied
+            self.client
+                .simple_query(format!("TRUNCATE TABLE {}", STAGE))
+                .await?
+                .into_results()
+                .await?;
+        }
+
+        let mut req = self.client.bulk_insert(STAGE).await?;
+        for row in rows {
+            let mut data = TokenRow::with_capacity(loaded.len());
+            for (value, column) in row.into_iter().zip(&loaded) {
+                data.push(bulk_data(value, column)?);
+            }
+            req.send(data).await?;
+        }
+        let total = req.finalize().await?.total();
+
+        let copy = match on_conflict {
+            OnConflict::Fail => format!(
+                "INSERT INTO {table} ({cols}) SELECT {cols} FROM {stage};",
+                table = table_name,
+                cols = columns,
+                stage = STAGE
+            ),
+            _ => merge_sql(
+                table_name,
+                &format!("{} AS s", STAGE),
+                target,
+                &names,
+                on_conflict,
+            ),
+        };
+        let sql_str = if written.iter().any(|x| x.identity) {
+            format!(
+                "SET IDENTITY_INSERT {table} ON; {copy} SET IDENTITY_INSERT {table} OFF;",
+                table = table_name,
+                copy = copy
+            )
+        } else {
+            copy
+        };
+        self.client
+            .simple_query(sql_str)
+            .await?
+            .into_results()
+            .await?;
+        Ok(total)
+    }
+}
+
+#[async_trait]
+impl Driver for MssqlDriver {
+    async fn show_dbs(&mut self) -> Result<Vec<String>> {
+        let row = self
+            .client
+            .simple_query(" SELECT  name FROM SysDatabases")
+            .await?
+            .into_first_result()
+            .await?;
+
+        Ok(row
+            .iter()
+            .filter_map(|x| x.get::<&str, _>(0).map(|r| r.to_string()))
+            .collect::<Vec<String>>())
+    }
+
+    async fn show_tables(&mut self, ddb: &str) -> Result<Vec<String>> {
+        let sql_str = format!(
+            "SELECT TABLE_SCHEMA + '.' + TABLE_NAME FROM {}.INFORMATION_SCHEMA.TABLES \
+             WHERE TABLE_TYPE = 'BASE TABLE' ORDER BY TABLE_SCHEMA, TABLE_NAME",
+            Dialect::Mssql.quote(ddb)
+        );
+        let row = self
+            .client
+            .simple_query(sql_str)
+            .await?
+            .into_first_result()
+            .await?;
+
+        Ok(row
+            .iter()
+            .filter_map(|x| x.get::<&str, _>(0).map(|r| r.to_string()))
+            .collect::<Vec<String>>())
+    }
+
+    async fn table_exists(&mut self, ddb: &str, table: &str) -> Result<bool> {
+        let (schema, name) = Dialect::Mssql.split_table(table);
+        let sql_str = format!(
+            "SELECT 1 FROM {}.INFORMATION_SCHEMA.TABLES WHERE TABLE_SCHEMA = @P1 AND TABLE_NAME = @P2",
+            Dialect::Mssql.quote(ddb)
+        );
+        let row = self
+            .client
+            .query(sql_str, &[&schema, &name])
+            .await?
+            .into_row()
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn table_detail(&mut self, ddb: &str, table: &str) -> Result<Vec<ColumnDetail>> {
+        let sql_str = format!(
+            "USE {};
+            SELECT
+                c.name,
+                t.name,
+                c.max_length,
+                c.precision,
+                c.scale,
+                c.is_nullable,
+                dc.definition,
+                c.is_identity,
+                CAST( CASE WHEN pk.column_id IS NULL THEN 0 ELSE 1 END AS bit ),
+                isnull( CAST( ep.[value] AS nvarchar(4000) ), '' ),
+                c.is_computed
+            FROM
+                sys.columns c
+                INNER JOIN sys.types t ON c.system_type_id = t.user_type_id
+                LEFT JOIN sys.default_constraints dc ON c.default_object_id = dc.object_id
+                LEFT JOIN sys.extended_properties ep ON ep.class = 1
+                AND ep.major_id = c.object_id
+                AND ep.minor_id = c.column_id
+                AND ep.name = 'MS_Description'
+                LEFT JOIN (
+                    SELECT ic.object_id, ic.column_id
+                    FROM sys.indexes i
+                    INNER JOIN sys.index_columns ic ON i.object_id = ic.object_id
+                    AND i.index_id = ic.index_id
+                    WHERE i.is_primary_key = 1
+                ) pk ON pk.object_id = c.object_id
+                AND pk.column_id = c.column_id
+            WHERE
+                c.object_id = OBJECT_ID( {} )
+            ORDER BY
+                c.column_id",
+            Dialect::Mssql.quote(ddb),
+            Dialect::Mssql.literal(&Dialect::Mssql.table_name(ddb, table)),
+        );
+        let row = self
+            .client
+            .simple_query(sql_str)
+            .await?
+            .into_first_result()
+            .await?;
+
+        let res = row
+            .iter()
+            .map(|x| {
+                let data_type = x.get::<&str, _>(1).unwrap_or_default().to_lowercase();
+                let length = x.get::<i16, _>(2).map(|x| x as i64);
+                let max_length = match data_type.as_str() {
+                    "nchar" | "nvarchar" => length.map(|x| if x > 0 { x / 2 } else { x }),
+                    "char" | "varchar" | "binary" | "varbinary" => length,
+                    _ => None,
+                };
+                let mut column = ColumnDetail {
+                    name: x.get::<&str, _>(0).unwrap_or_default().to_string(),
+                    data_type,
+                    column_type: String::new(),
+                    max_length,
+                    precision: x.get::<u8, _>(3).map(|x| x as i64),
+                    scale: x.get::<u8, _>(4).map(|x| x as i64),
+                    nullable: x.get::<bool, _>(5).unwrap_or(true),
+                    default: x.get::<&str, _>(6).map(|x| x.to_string()),
+                    identity: x.get::<bool, _>(7).unwrap_or(false),
+                    computed: x.get::<bool, _>(10).unwrap_or(false),
+                    primary_key: x.get::<bool, _>(8).unwrap_or(false),
+                    comment: x.get::<&str, _>(9).unwrap_or_default().to_string(),
+                };
+                column.column_type = column.mssql_type();
+                column
+            })
+            .collect::<Vec<ColumnDetail>>();
+        Ok(res)
+    }
+
+    async fn read_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        filter: &Filter,
+        batch_size: usize,
+        tx: Sender<Batch>,
+    ) -> Result<u64> {
+        let sql_str = format!(
+            "SELECT {} FROM {}{}",
+            columns
+                .iter()
+                .map(|x| Dialect::Mssql.quote(x))
+                .collect::<Vec<String>>()
+                .join(", "),
+            Dialect::Mssql.table_name(ddb, table),
+            filter.clause()
+        );
+        self.read_query(sql_str, batch_size, tx).await
+    }
+
+    /// Loads `rows` with a TDS bulk insert, through the stage for columns
+    /// such as `money` or `text` that it cannot encode, and with one
+    /// parameterised `INSERT` per row for other types it does not know, such
+    /// as `sql_variant` or spatial types. Rows to update or skip go through
+    /// the stage and a `MERGE`.
+    async fn write_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        let table_name = Dialect::Mssql.table_name(ddb, table);
+        if !self.targets.contains_key(&table_name) {
+            let detail = self.table_detail(ddb, table).await?;
+            self.targets.insert(table_name.clone(), detail);
+        }
+        let target = self.targets[&table_name].clone();
+
+        // target column for each source column, in source order
+        let written = columns
+            .iter()
+            .map(|name| target.iter().find(|x| x.name.eq_ignore_ascii_case(name)))
+            .collect::<Option<Vec<&ColumnDetail>>>();
+        match written {
+            Some(written) if written.iter().all(|x| bulk_supported(&x.data_type)) => {
+                if written.iter().any(|x| x.identity) || *on_conflict != OnConflict::Fail {
+                    self.bulk_staged(&table_name, &target, &written, rows, on_conflict)
+                        .await
+                } else {
+                    self.bulk_direct(&table_name, &target, &written, rows).await
+                }
+            }
+            Some(written)
+                if written
+                    .iter()
+                    .all(|x| bulk_supported(&x.data_type) || stage_column(x).is_some()) =>
+            {
+                self.bulk_staged(&table_name, &target, &written, rows, on_conflict)
+                    .await
+            }
+            _ => {
+                self.insert_rows(&table_name, &target, columns, rows, on_conflict)
+                    .await
+            }
+        }
+    }
+
+    async fn query(&mut self, sql_str: &str) -> Result<Vec<Vec<Value>>> {
+        let rows = self
+            .client
+            .simple_query(sql_str)
+            .await?
+            .into_first_result()
+            .await?
+            .into_iter()
+            .map(|row| row.into_iter().map(Value::from).collect::<Vec<Value>>())
+            .collect::<Vec<Vec<Value>>>();
+        Ok(rows)
+    }
+
+    async fn execute(&mut self, statements: &[String]) -> Result<()> {
+        for sql_str in statements {
+            log_statement(sql_str);
+            self.client.execute(sql_str.as_str(), &[]).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
+        self.client.close().await?;
+        Ok(())
+    }
+}
+
+/// `MERGE` of the rows of `source`, aliased `s`, into `table_name` matched on
+/// the key of `on_conflict`. Identity columns are never updated.
+fn merge_sql(
+    table_name: &str,
+    source: &str,
+    target: &[ColumnDetail],
+    columns: &[String],
+    on_conflict: &OnConflict,
+) -> String {
+    let on = on_conflict
+        .key()
+        .iter()
+        .map(|x| format!("t.{0} = s.{0}", Dialect::Mssql.quote(x)))
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    let updated = on_conflict
+        .updated(columns)
+        .into_iter()
+        .filter(|name| {
+            !target
+                .iter()
+                .any(|x| x.identity && x.name.eq_ignore_ascii_case(name))
+        })
+        .map(|x| format!("t.{0} = s.{0}", Dialect::Mssql.quote(x)))
+        .collect::<Vec<String>>();
+    let matched = if updated.is_empty() {
+        String::new()
+    } else {
+        format!(" WHEN MATCHED THEN UPDATE SET {}", updated.join(", "))
+    };
+    format!(
+        "MERGE {} WITH (HOLDLOCK) AS t USING {} ON {}{} WHEN NOT MATCHED THEN INSERT ({}) VALUES ({});",
+        table_name,
+        source,
+        on,
+        matched,
+        columns
+            .iter()
+            .map(|x| Dialect::Mssql.quote(x))
+            .collect::<Vec<String>>()
+            .join(", "),
+        columns
+            .iter()
+            .map(|x| format!("s.{}", Dialect::Mssql.quote(x)))
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+/// Types `bulk_data` can encode for a bulk insert.
+fn bulk_supported(data_type: &str) -> bool {
+    matches!(
+        data_type,
+        "bit"
+            | "tinyint"
+            | "smallint"
+            | "int"
+            | "bigint"
+            | "real"
+            | "float"
+            | "decimal"
+            | "numeric"
+            | "char"
+            | "varchar"
+            | "nchar"
+            | "nvarchar"
+            | "binary"
+            | "varbinary"
+            | "uniqueidentifier"
+            | "date"
+            | "time"
+            | "datetime"
+            | "smalldatetime"
+            | "datetime2"
+            | "datetimeoffset"
+    )
+}
+
+/// Column `column` is staged as when a bulk load cannot encode its type.
+fn stage_column(column: &ColumnDetail) -> Option<ColumnDetail> {
+    let (data_type, max_length, precision, scale) = match column.data_type.as_str() {
+        "money" => ("decimal", None, Some(19), Some(4)),
+        "smallmoney" => ("decimal", None, Some(10), Some(4)),
+        "text" => ("varchar", Some(-1), None, None),
+        "ntext" | "xml" => ("nvarchar", Some(-1), None, None),
+        "image" => ("varbinary", Some(-1), None, None),
+        _ => return None,
+    };
+    let mut stage = ColumnDetail {
+        data_type: data_type.to_string(),
+        max_length,
+        precision,
+        scale,
+        ..column.clone()
+    };
+    stage.column_type = stage.mssql_type();
+    Some(stage)
+}
+
+fn to_i64(value: &Value) -> Option<i64> {
+    match value {
+        Value::Int(x) => Some(*x),
+        Value::UInt(x) => i64::try_from(*x).ok(),
+        Value::Bool(x) => Some(*x as i64),
+        Value::Float(x) if x.fract() == 0.0 => Some(*x as i64),
+        Value::Text(x) | Value::Decimal(x) => x.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(x) => Some(*x as f64),
+        Value::UInt(x) => Some(*x as f64),
+        Value::Float(x) => Some(*x),
+        Value::Text(x) | Value::Decimal(x) => x.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn to_text(value: Value) -> Option<String> {
+    match value {
+        Value::Text(x) | Value::Decimal(x) => Some(x),
+        Value::Int(x) => Some(x.to_string()),
+        Value::UInt(x) => Some(x.to_string()),
+        Value::Float(x) => Some(x.to_string()),
+        Value::Bool(x) => Some((x as u8).to_string()),
+        Value::Date(x) => Some(x.format("%Y-%m-%d").to_string()),
+        Value::DateTime(x) => Some(x.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        Value::Time(x) => Some(x.format("%H:%M:%S%.f").to_string()),
+        Value::Bytes(x) => String::from_utf8(x).ok(),
+        Value::Null => None,
+    }
+}
+
+fn to_datetime(value: &Value) -> Option<NaiveDateTime> {
+    match value {
+        Value::DateTime(x) => Some(*x),
+        Value::Date(x) => x.and_hms_opt(0, 0, 0),
+        Value::Text(x) => {
+            let x = x.trim();
+            NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f")
+                .or_else(|_| NaiveDateTime::parse_from_str(x, "%Y-%m-%dT%H:%M:%S%.f"))
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(x, "%Y-%m-%d")
+                        .ok()?
+                        .and_hms_opt(0, 0, 0)
+                })
+        }
+        _ => None,
+    }
+}
+
+fn to_time(value: &Value) -> Option<NaiveTime> {
+    match value {
+        Value::Time(x) => Some(*x),
+        Value::DateTime(x) => Some(x.time()),
+        Value::Text(x) => NaiveTime::parse_from_str(x.trim(), "%H:%M:%S%.f").ok(),
+        _ => None,
+    }
+}
+
+/// Exact numeric with exactly `scale` digits after the point; extra digits
+/// are cut off.
+fn to_numeric(value: &Value, scale: u8) -> Option<Numeric> {
+    let text = match value {
+        Value::Float(x) => format!("{:.*}", scale as usize, x),
+        Value::Int(x) => x.to_string(),
+        Value::UInt(x) => x.to_string(),
+        Value::Text(x) | Value::Decimal(x) => x.trim().to_string(),
+        _ => return None,
+    };
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.trim_start_matches('+')),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    let frac = frac_part
+        .chars()
+        .chain(std::iter::repeat('0'))
+        .take(scale as usize)
+        .collect::<String>();
+    let all = format!("{}{}", int_part, frac);
+    if all.is_empty() || !all.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let value = all.parse::<i128>().ok()?;
+    Some(Numeric::new_with_scale(
+        if negative { -value } else { value },
+        scale,
+    ))
+}
+
+fn tds_time(time: NaiveTime, scale: u8) -> Time {
+    let nanos = time.num_seconds_from_midnight() as u64 * 1_000_000_000 + time.nanosecond() as u64;
+    Time::new(nanos / 10u64.pow(9 - scale as u32), scale)
+}
+
+fn tds_date(date: NaiveDate) -> Date {
+    Date::new(date.num_days_from_ce() as u32 - 1)
+}
+
+/// NULL of the type of `column`.
+fn null_data(column: &ColumnDetail) -> ColumnData<'static> {
+    match column.data_type.as_str() {
+        "binary" | "varbinary" | "image" | "timestamp" | "rowversion" => ColumnData::Binary(None),
+        _ => bulk_data(Value::Null, column).unwrap_or(ColumnData::String(None)),
+    }
+}
+
+/// A NULL parameter bound as the type of its column.
+struct TypedNull(ColumnData<'static>);
+
+impl ToSql for TypedNull {
+    fn to_sql(&self) -> ColumnData<'_> {
+        self.0.clone()
+    }
+}
+
+/// `value` encoded as exactly the type of `column`, which a bulk insert
+/// requires.
+fn bulk_data(value: Value, column: &ColumnDetail) -> Result<ColumnData<'static>> {
+    let scale = column.scale.unwrap_or(7).clamp(0, 7) as u8;
+    let null = value == Value::Null;
+    let invalid = || {
+        Error::Mapping(format!(
+            "{}: cannot load {:?} into {}",
+            column.name, value, column.column_type
+        ))
+    };
+    macro_rules! convert {
+        ($variant:ident, $expr:expr) => {
+            if null {
+                ColumnData::$variant(None)
+            } else {
+                ColumnData::$variant(Some($expr.ok_or_else(invalid)?))
+            }
+        };
+    }
+
+    let data = match column.data_type.as_str() {
+        "bit" => convert!(Bit, to_i64(&value).map(|x| x != 0)),
+        "tinyint" => convert!(U8, to_i64(&value).and_then(|x| u8::try_from(x).ok())),
+        "smallint" => convert!(I16, to_i64(&value).and_then(|x| i16::try_from(x).ok())),
+        "int" => convert!(I32, to_i64(&value).and_then(|x| i32::try_from(x).ok())),
+        "bigint" => convert!(I64, to_i64(&value)),
+        "real" => convert!(F32, to_f64(&value).map(|x| x as f32)),
+        "float" => convert!(F64, to_f64(&value)),
+        "decimal" | "numeric" => {
+            let scale = column.scale.unwrap_or(0) as u8;
+            convert!(Numeric, to_numeric(&value, scale))
+        }
+        "uniqueidentifier" => convert!(
+            Guid,
+            to_text(value.clone()).and_then(|x| Uuid::parse_str(x.trim()).ok())
+        ),
+        "binary" | "varbinary" => match value {
+            Value::Null => ColumnData::Binary(None),
+            Value::Bytes(x) => ColumnData::Binary(Some(Cow::Owned(x))),
+            value => ColumnData::Binary(to_text(value).map(|x| Cow::Owned(x.into_bytes()))),
+        },
+        "date" => convert!(Date, to_datetime(&value).map(|x| tds_date(x.date()))),
+        "time" => convert!(Time, to_time(&value).map(|x| tds_time(x, scale))),
+        "datetime2" => convert!(
+            DateTime2,
+            to_datetime(&value)
+                .map(|x| DateTime2::new(tds_date(x.date()), tds_time(x.time(), scale)))
+        ),
+        "datetimeoffset" => convert!(
+            DateTimeOffset,
+            to_datetime(&value).map(|x| DateTimeOffset::new(
+                DateTime2::new(tds_date(x.date()), tds_time(x.time(), scale)),
+                0
+            ))
+        ),
+        // days since 1900 and 1/300 second ticks
+        "datetime" => convert!(
+            DateTime,
+            to_datetime(&value).map(|x| {
+                let days = (x.date() - NaiveDate::from_ymd_opt(1900, 1, 1).unwrap()).num_days();
+                let ms =
+                    x.num_seconds_from_midnight() as u64 * 1000 + x.nanosecond() as u64 / 1_000_000;
+                TdsDateTime::new(days as i32, ((ms * 3 + 5) / 10) as u32)
+            })
+        ),
+        // days since 1900 and minutes
+        "smalldatetime" => convert!(
+            SmallDateTime,
+            to_datetime(&value).and_then(|x| {
+                let days = (x.date() - NaiveDate::from_ymd_opt(1900, 1, 1).unwrap()).num_days();
+                let minutes = (x.num_seconds_from_midnight() + 30) / 60;
+                // 23:59:30 and later round up to midnight of the next day
+                Some(SmallDateTime::new(
+                    u16::try_from(days + minutes as i64 / 1440).ok()?,
+                    (minutes % 1440) as u16,
+                ))
+            })
+        ),
+        _ => ColumnData::String(to_text(value).map(Cow::Owned)),
+    };
+    Ok(data)
+}
@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use mysql_async::prelude::{LocalInfileHandler, Queryable};
+use mysql_async::{BinlogRequest, BinlogStream, Conn, InfileHandlerFuture, OptsBuilder, Pool};
+use tokio::sync::mpsc::Sender;
+
+use super::{log_statement, Batch, Driver, ErrorClass, Filter, OnConflict};
+use crate::db::{ColumnDetail, DatabaseConfig, Dialect};
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+/// Serves the buffers registered by `write_rows` to `LOAD DATA LOCAL
+/// INFILE`, and nothing else, so the server cannot ask for real files.
+#[derive(Clone, Default)]
+struct InfileBuffers(Arc<Mutex<HashMap<String, Vec<u8>>>>);
+
+impl LocalInfileHandler for InfileBuffers {
+    fn handle(&self, file_name: &[u8]) -> InfileHandlerFuture {
+        let name = String::from_utf8_lossy(file_name).into_owned();
+        let data = self.0.lock().unwrap().remove(&name);
+        Box::pin(async move {
+            match data {
+                Some(data) => Ok(Box::new(io::Cursor::new(data)) as Box<_>),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no buffer named {}", name),
+                )
+                .into()),
+            }
+        })
+    }
+}
+
+pub struct MysqlDriver {
+    conn: Conn,
+    pool: Pool,
+    /// server `max_allowed_packet`, which bounds one multi-row insert
+    max_packet: usize,
+    /// write with `LOAD DATA LOCAL INFILE` instead of inserts
+    load_data: bool,
+    buffers: InfileBuffers,
+    loads: u64,
+}
+
+impl MysqlDriver {
+    pub async fn connect(db: &DatabaseConfig) -> Result<Self> {
+        let buffers = InfileBuffers::default();
+        let opts = OptsBuilder::default()
+            .ip_or_hostname(db.host.as_str())
+            .tcp_port(db.port)
+            .user(Some(db.user.as_str()))
+            .pass(Some(db.password.as_str()))
+            .db_name(Some(db.default_db.as_str()))
+            .local_infile_handler(Some(buffers.clone()));
+        let pool = Pool::new(opts);
+        let mut conn = pool.get_conn().await?;
+        let max_packet = conn
+            .query_first::<usize, _>("SELECT @@max_allowed_packet")
+            .await?
+            .unwrap_or(4 << 20);
+        Ok(Self {
+            conn,
+            pool,
+            max_packet,
+            load_data: db.load_data,
+            buffers,
+            loads: 0,
+        })
+    }
+
+    /// Loads `rows` as one tab separated buffer. Returns `None` when the
+    /// server refuses local infile, leaving the rows to the insert path, and
+    /// fails when a row was dropped or a value changed on the way in.
+    async fn load_data(
+        &mut self,
+        table_name: &str,
+        columns: &[String],
+        rows: &[Vec<Value>],
+        on_conflict: &OnConflict,
+    ) -> Result<Option<u64>> {
+        let mut buf = vec![];
+        for row in rows {
+            for (idx, value) in row.iter().enumerate() {
+                if idx > 0 {
+                    buf.push(b'\t');
+                }
+                value.write_tsv(&mut buf);
+            }
+            buf.push(b'\n');
+        }
+
+        self.loads += 1;
+        let name = format!("sql_transfer_{}", self.loads);
+        self.buffers.0.lock().unwrap().insert(name.clone(), buf);
+        // LOAD DATA matches rows on any unique key, not just `on_conflict`'s
+        let modifier = match on_conflict {
+            OnConflict::Fail => "",
+            OnConflict::Update(_) => unreachable!("updates take the insert path"),
+            OnConflict::Skip(_) => "IGNORE ",
+        };
+        let sql_str = format!(
+            "LOAD DATA LOCAL INFILE {} {}INTO TABLE {} CHARACTER SET utf8mb4 ({})",
+            Dialect::Mysql.literal(&name),
+            modifier,
+            table_name,
+            columns
+                .iter()
+                .map(|x| Dialect::Mysql.quote(x))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        let res = self.conn.query_drop(sql_str).await;
+        self.buffers.0.lock().unwrap().remove(&name);
+        match res {
+            Ok(()) => {
+                // LOAD DATA LOCAL skips duplicate keys and truncates values
+                // that do not convert, as if IGNORE were given, leaving only
+                // warnings behind
+                let written = self.conn.affected_rows();
+                let warnings = if self.conn.get_warnings() > 0 {
+                    self.conn
+                        .query::<(String, u32, String), _>("SHOW WARNINGS LIMIT 5")
+                        .await?
+                        .into_iter()
+                        .filter(|(_, code, _)| *on_conflict == OnConflict::Fail || *code != 1062)
+                        .map(|(_, _, message)| message)
+                        .collect::<Vec<String>>()
+                } else {
+                    vec![]
+                };
+                let short = *on_conflict == OnConflict::Fail && written != rows.len() as u64;
+                if short || !warnings.is_empty() {
+                    return Err(Error::Query(
+                        format!(
+                            "LOAD DATA wrote {} of {} rows: {}",
+                            written,
+                            rows.len(),
+                            warnings.join("; ")
+                        )
+                        .into(),
+                    ));
+                }
+                Ok(Some(written))
+            }
+            // 1148: not allowed with this version, 3948: local_infile disabled
+            Err(mysql_async::Error::Server(e)) if e.code == 1148 || e.code == 3948 => {
+                eprintln!("{}, falling back to inserts", e.message);
+                self.load_data = false;
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write_batch(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        let table_name = Dialect::Mysql.table_name(ddb, table);
+        // affected rows count updated rows twice, so count what was sent
+        let sent = rows.len() as u64;
+        // REPLACE would delete the old row rather than update it, firing
+        // delete triggers and cascades and resetting unlisted columns
+        if self.load_data && !matches!(on_conflict, OnConflict::Update(_)) {
+            if let Some(written) = self
+                .load_data(&table_name, columns, &rows, on_conflict)
+                .await?
+            {
+                return Ok(if *on_conflict == OnConflict::Fail {
+                    written
+                } else {
+                    sent
+                });
+            }
+        }
+
+        let head = format!(
+            "INSERT {}INTO {} ({}) VALUES",
+            if let OnConflict::Skip(_) = on_conflict {
+                "IGNORE "
+            } else {
+                ""
+            },
+            table_name,
+            columns
+                .iter()
+                .map(|x| Dialect::Mysql.quote(x))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        let tail = match on_conflict {
+            OnConflict::Update(key) => {
+                let updated = on_conflict.updated(columns);
+                // a key-only table has nothing to update but must not fail
+                let columns = if updated.is_empty() {
+                    key.iter().collect()
+                } else {
+                    updated
+                };
+                format!(
+                    " ON DUPLICATE KEY UPDATE {}",
+                    columns
+                        .iter()
+                        .map(|x| format!("{0} = VALUES({0})", Dialect::Mysql.quote(x)))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            _ => String::new(),
+        };
+        let width = columns.len();
+        // a prepared statement takes at most 65535 placeholders
+        let max_rows = (u16::MAX as usize / width).max(1);
+        let budget = self
+            .max_packet
+            .saturating_sub(head.len() + tail.len() + 1024);
+
+        let mut written = 0;
+        let mut params = Vec::with_capacity(rows.len().min(max_rows) * width);
+        let mut size = 0;
+        for row in rows {
+            let row_size = row.iter().map(packet_size).sum::<usize>();
+            if !params.is_empty() && (params.len() / width >= max_rows || size + row_size > budget)
+            {
+                written += self
+                    .insert(&head, &tail, width, std::mem::take(&mut params))
+                    .await?;
+                size = 0;
+            }
+            size += row_size;
+            params.extend(row.into_iter().map(mysql_async::Value::from));
+        }
+        if !params.is_empty() {
+            written += self.insert(&head, &tail, width, params).await?;
+        }
+        Ok(if *on_conflict == OnConflict::Fail {
+            written
+        } else {
+            sent
+        })
+    }
+
+    /// Runs one `INSERT` carrying all of `params`, `width` values per row.
+    async fn insert(
+        &mut self,
+        head: &str,
+        tail: &str,
+        width: usize,
+        params: Vec<mysql_async::Value>,
+    ) -> Result<u64> {
+        let row = format!("({})", vec!["?"; width].join(", "));
+        let rows = vec![row; params.len() / width].join(", ");
+        let sql_str = format!("{} {}{}", head, rows, tail);
+        self.conn.exec_drop(sql_str, params).await?;
+        Ok(self.conn.affected_rows())
+    }
+}
+
+/// Connects as replica `server_id` and streams binlog events from `pos` in
+/// `file` on.
+pub async fn binlog_stream(
+    db: &DatabaseConfig,
+    server_id: u32,
+    file: &str,
+    pos: u64,
+) -> Result<BinlogStream> {
+    let opts = OptsBuilder::default()
+        .ip_or_hostname(db.host.as_str())
+        .tcp_port(db.port)
+        .user(Some(db.user.as_str()))
+        .pass(Some(db.password.as_str()));
+    let conn = Conn::new(opts)
+        .await
+        .map_err(|e| Error::from(e).connect(db.label()))?;
+    let request = BinlogRequest::new(server_id)
+        .with_filename(file.as_bytes())
+        .with_pos(pos);
+    Ok(conn.get_binlog_stream(request).await?)
+}
+
+pub(super) fn error_class(e: &(dyn std::error::Error + 'static)) -> Option<ErrorClass> {
+    use mysql_async::{DriverError, Error};
+    match e.downcast_ref::<Error>()? {
+        Error::Io(_) => Some(ErrorClass::Network),
+        Error::Driver(DriverError::ConnectionClosed | DriverError::PoolDisconnected) => {
+            Some(ErrorClass::Network)
+        }
+        Error::Server(e) => match e.code {
+            1213 => Some(ErrorClass::Deadlock),
+            1205 => Some(ErrorClass::LockTimeout),
+            // too many connections, server shutting down, connection lost
+            1040 | 1053 | 2006 | 2013 => Some(ErrorClass::Network),
+            _ => Some(ErrorClass::Permanent),
+        },
+        _ => Some(ErrorClass::Permanent),
+    }
+}
+
+/// Rough size of a value inside a prepared statement packet.
+fn packet_size(value: &Value) -> usize {
+    let data = match value {
+        Value::Null => 0,
+        Value::Text(x) | Value::Decimal(x) => x.len() + 9,
+        Value::Bytes(x) => x.len() + 9,
+        _ => 12,
+    };
+    // two bytes of parameter type each
+    data + 2
+}
+
+/// `COLUMN_DEFAULT` as SQL. MySQL lists literal defaults bare, e.g. `abc`,
+/// where MariaDB quotes them, and both list functions such as
+/// `CURRENT_TIMESTAMP` as they are.
+fn default_sql(default: Option<String>, extra: &str) -> Option<String> {
+    let default = default?;
+    let bare = !extra.to_uppercase().contains("DEFAULT_GENERATED")
+        && !default.starts_with('\'')
+        && default.parse::<f64>().is_err()
+        && !default.to_uppercase().starts_with("CURRENT_TIMESTAMP")
+        && default != "NULL";
+    Some(if bare {
+        format!("'{}'", default.replace('\'', "''"))
+    } else {
+        default
+    })
+}
+
+#[async_trait]
+impl Driver for MysqlDriver {
+    async fn show_dbs(&mut self) -> Result<Vec<String>> {
+        let mut result = self.conn.query_iter("show databases").await?;
+        Ok(result.collect::<String>().await?)
+    }
+
+    async fn show_tables(&mut self, ddb: &str) -> Result<Vec<String>> {
+        let mut result = self
+            .conn
+            .exec_iter(
+                "select table_name from information_schema.tables where table_schema = ? and table_type = 'base table'",
+                (ddb,),
+            )
+            .await?;
+        Ok(result.collect::<String>().await?)
+    }
+
+    async fn table_exists(&mut self, ddb: &str, table: &str) -> Result<bool> {
+        let (_, name) = Dialect::Mysql.split_table(table);
+        let row: Option<u8> = self
+            .conn
+            .exec_first(
+                "select 1 from information_schema.tables where table_schema = ? and table_name = ?",
+                (ddb, name),
+            )
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn table_detail(&mut self, ddb: &str, table: &str) -> Result<Vec<ColumnDetail>> {
+        let (_, name) = Dialect::Mysql.split_table(table);
+        let sql_str = "SELECT
+                    COLUMN_NAME,
+                    DATA_TYPE,
+                    COLUMN_TYPE,
+                    CHARACTER_MAXIMUM_LENGTH,
+                    NUMERIC_PRECISION,
+                    IFNULL( NUMERIC_SCALE, DATETIME_PRECISION ),
+                    IS_NULLABLE,
+                    COLUMN_DEFAULT,
+                    EXTRA,
+                    COLUMN_KEY,
+                    COLUMN_COMMENT
+                FROM
+                    information_schema.`COLUMNS`
+                WHERE
+                    TABLE_SCHEMA = ?
+                    AND TABLE_NAME = ?
+                ORDER BY
+                    ORDINAL_POSITION";
+        let mut result = self.conn.exec_iter(sql_str, (ddb, name)).await?;
+        let res = result
+            .collect::<(
+                String,
+                String,
+                String,
+                Option<i64>,
+                Option<i64>,
+                Option<i64>,
+                String,
+                Option<String>,
+                String,
+                String,
+                String,
+            )>()
+            .await?
+            .into_iter()
+            .map(|x| ColumnDetail {
+                name: x.0,
+                data_type: x.1.to_lowercase(),
+                column_type: x.2.to_lowercase(),
+                max_length: x.3,
+                precision: x.4,
+                scale: x.5,
+                nullable: x.6 == "YES",
+                default: default_sql(x.7, &x.8),
+                identity: x.8.contains("auto_increment"),
+                computed: ["VIRTUAL GENERATED", "STORED GENERATED"]
+                    .iter()
+                    .any(|g| x.8.to_uppercase().contains(g)),
+                primary_key: x.9 == "PRI",
+                comment: x.10,
+            })
+            .collect::<Vec<ColumnDetail>>();
+        Ok(res)
+    }
+
+    async fn read_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        filter: &Filter,
+        batch_size: usize,
+        tx: Sender<Batch>,
+    ) -> Result<u64> {
+        let sql_str = format!(
+            "SELECT {} FROM {}{}",
+            columns
+                .iter()
+                .map(|x| Dialect::Mysql.quote(x))
+                .collect::<Vec<String>>()
+                .join(", "),
+            Dialect::Mysql.table_name(ddb, table),
+            filter.clause()
+        );
+        let mut result = self.conn.exec_iter(sql_str, ()).await?;
+        let meta = result.columns_ref().to_vec();
+        let mut read = 0;
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(row) = result.next().await? {
+            batch.push(
+                row.unwrap()
+                    .into_iter()
+                    .zip(meta.iter())
+                    .map(|(v, col)| Value::from_mysql(v, col))
+                    .collect::<Vec<Value>>(),
+            );
+            if batch.len() >= batch_size {
+                read += batch.len() as u64;
+                tx.send(std::mem::replace(&mut batch, Vec::with_capacity(batch_size)))
+                    .await?;
+            }
+        }
+        if !batch.is_empty() {
+            read += batch.len() as u64;
+            tx.send(batch).await?;
+        }
+        Ok(read)
+    }
+
+    /// Writes in one transaction, so a batch failing halfway leaves no rows
+    /// behind to be written twice when it is retried.
+    async fn write_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        self.conn.query_drop("START TRANSACTION").await?;
+        match self
+            .write_batch(ddb, table, columns, rows, on_conflict)
+            .await
+        {
+            Ok(written) => {
+                self.conn.query_drop("COMMIT").await?;
+                Ok(written)
+            }
+            Err(e) => {
+                // the connection may be gone, which rolls back as well
+                let _ = self.conn.query_drop("ROLLBACK").await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn query(&mut self, sql_str: &str) -> Result<Vec<Vec<Value>>> {
+        let mut result = self.conn.exec_iter(sql_str, ()).await?;
+        let meta = result.columns_ref().to_vec();
+        let rows = result
+            .collect::<mysql_async::Row>()
+            .await?
+            .into_iter()
+            .map(|row| {
+                row.unwrap()
+                    .into_iter()
+                    .zip(meta.iter())
+                    .map(|(v, col)| Value::from_mysql(v, col))
+                    .collect::<Vec<Value>>()
+            })
+            .collect::<Vec<Vec<Value>>>();
+        Ok(rows)
+    }
+
+    async fn execute(&mut self, statements: &[String]) -> Result<()> {
+        for sql_str in statements {
+            log_statement(sql_str);
+            self.conn.query_drop(sql_str).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
+        drop(self.conn);
+        self.pool.disconnect().await?;
+        Ok(())
+    }
+}
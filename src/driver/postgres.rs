@@ -0,0 +1,446 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use futures::{pin_mut, SinkExt, TryStreamExt};
+use tokio::sync::mpsc::Sender;
+use tokio_postgres::error::{DbError, SqlState};
+use tokio_postgres::{types::Type, Client, CopyInSink, NoTls, Row, SimpleQueryMessage};
+
+use super::{log_statement, Batch, Driver, ErrorClass, Filter, OnConflict};
+use crate::db::{ColumnDetail, DatabaseConfig, Dialect};
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+/// flush the COPY buffer to the server once it grows past this many bytes
+const COPY_CHUNK: usize = 1 << 20;
+
+/// temporary table rows are copied into before being merged into the target
+const STAGE: &str = "sql_transfer_stage";
+
+pub struct PostgresDriver {
+    client: Client,
+}
+
+impl PostgresDriver {
+    pub async fn connect(db: &DatabaseConfig) -> Result<Self> {
+        let mut config = tokio_postgres::Config::new();
+        config
+            .host(&db.host)
+            .port(db.port)
+            .user(&db.user)
+            .password(&db.password)
+            .dbname(&db.default_db);
+
+        let (client, connection) = config.connect(NoTls).await.map_err(pg_err)?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("postgres connection: {}", e);
+            }
+        });
+        Ok(Self { client })
+    }
+}
+
+/// The server error when there is one; `tokio_postgres::Error` alone only
+/// says `db error`.
+fn pg_err(e: tokio_postgres::Error) -> Error {
+    match e.as_db_error() {
+        Some(db) => db.clone().into(),
+        None => e.into(),
+    }
+}
+
+/// Streams `rows` through an open `COPY ... FROM STDIN`.
+async fn send_copy(sink: CopyInSink<Bytes>, rows: Vec<Vec<Value>>) -> Result<u64> {
+    pin_mut!(sink);
+    let mut buf = String::new();
+    for row in rows {
+        for (idx, value) in row.iter().enumerate() {
+            if idx > 0 {
+                buf.push('\t');
+            }
+            value.write_copy(&mut buf);
+        }
+        buf.push('\n');
+        if buf.len() >= COPY_CHUNK {
+            sink.send(Bytes::from(std::mem::take(&mut buf)))
+                .await
+                .map_err(pg_err)?;
+        }
+    }
+    if !buf.is_empty() {
+        sink.send(Bytes::from(buf)).await.map_err(pg_err)?;
+    }
+    sink.finish().await.map_err(pg_err)
+}
+
+pub(super) fn error_class(e: &(dyn std::error::Error + 'static)) -> Option<ErrorClass> {
+    let code = match (
+        e.downcast_ref::<DbError>(),
+        e.downcast_ref::<tokio_postgres::Error>(),
+    ) {
+        (Some(db), _) => db.code(),
+        (None, Some(e)) if e.is_closed() => return Some(ErrorClass::Network),
+        (None, Some(e)) => e.code()?,
+        (None, None) => return None,
+    };
+    let class = match *code {
+        SqlState::T_R_DEADLOCK_DETECTED | SqlState::T_R_SERIALIZATION_FAILURE => {
+            ErrorClass::Deadlock
+        }
+        SqlState::LOCK_NOT_AVAILABLE => ErrorClass::LockTimeout,
+        SqlState::CONNECTION_EXCEPTION
+        | SqlState::CONNECTION_FAILURE
+        | SqlState::SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION
+        | SqlState::ADMIN_SHUTDOWN
+        | SqlState::CRASH_SHUTDOWN
+        | SqlState::CANNOT_CONNECT_NOW
+        | SqlState::TOO_MANY_CONNECTIONS => ErrorClass::Network,
+        _ => ErrorClass::Permanent,
+    };
+    Some(class)
+}
+
+/// Whether values of this type can be decoded directly; everything else is
+/// selected as text.
+fn is_native(ty: &Type) -> bool {
+    [
+        Type::BOOL,
+        Type::INT2,
+        Type::INT4,
+        Type::INT8,
+        Type::FLOAT4,
+        Type::FLOAT8,
+        Type::TEXT,
+        Type::VARCHAR,
+        Type::BPCHAR,
+        Type::NAME,
+        Type::BYTEA,
+        Type::DATE,
+        Type::TIME,
+        Type::TIMESTAMP,
+        Type::TIMESTAMPTZ,
+    ]
+    .contains(ty)
+}
+
+fn value_at(row: &Row, idx: usize, ty: &Type) -> Result<Value> {
+    let value = match *ty {
+        Type::BOOL => row.try_get::<_, Option<bool>>(idx)?.map(Value::Bool),
+        Type::INT2 => row
+            .try_get::<_, Option<i16>>(idx)?
+            .map(|x| Value::Int(x as i64)),
+        Type::INT4 => row
+            .try_get::<_, Option<i32>>(idx)?
+            .map(|x| Value::Int(x as i64)),
+        Type::INT8 => row.try_get::<_, Option<i64>>(idx)?.map(Value::Int),
+        Type::FLOAT4 => row
+            .try_get::<_, Option<f32>>(idx)?
+            .map(|x| Value::Float(x as f64)),
+        Type::FLOAT8 => row.try_get::<_, Option<f64>>(idx)?.map(Value::Float),
+        Type::BYTEA => row.try_get::<_, Option<Vec<u8>>>(idx)?.map(Value::Bytes),
+        Type::DATE => row.try_get::<_, Option<NaiveDate>>(idx)?.map(Value::Date),
+        Type::TIME => row.try_get::<_, Option<NaiveTime>>(idx)?.map(Value::Time),
+        Type::TIMESTAMP => row
+            .try_get::<_, Option<NaiveDateTime>>(idx)?
+            .map(Value::DateTime),
+        Type::TIMESTAMPTZ => row
+            .try_get::<_, Option<DateTime<Utc>>>(idx)?
+            .map(|x| Value::DateTime(x.naive_utc())),
+        _ => row.try_get::<_, Option<String>>(idx)?.map(Value::Text),
+    };
+    Ok(value.unwrap_or(Value::Null))
+}
+
+#[async_trait]
+impl Driver for PostgresDriver {
+    /// Lists schemas, which play the part of databases for this backend.
+    async fn show_dbs(&mut self) -> Result<Vec<String>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT schema_name::text FROM information_schema.schemata \
+                 WHERE schema_name NOT IN ('pg_catalog', 'information_schema') \
+                 AND schema_name NOT LIKE 'pg\\_%' ORDER BY 1",
+                &[],
+            )
+            .await
+            .map_err(pg_err)?;
+        Ok(rows.iter().map(|x| x.get::<_, String>(0)).collect())
+    }
+
+    async fn show_tables(&mut self, ddb: &str) -> Result<Vec<String>> {
+        let rows = self
+            .client
+            .query(
+                "SELECT table_name::text FROM information_schema.tables \
+                 WHERE table_schema = $1 AND table_type = 'BASE TABLE' ORDER BY 1",
+                &[&ddb],
+            )
+            .await
+            .map_err(pg_err)?;
+        Ok(rows.iter().map(|x| x.get::<_, String>(0)).collect())
+    }
+
+    async fn table_exists(&mut self, ddb: &str, table: &str) -> Result<bool> {
+        let (_, name) = Dialect::Postgres.split_table(table);
+        let row = self
+            .client
+            .query_opt(
+                "SELECT 1 FROM information_schema.tables WHERE table_schema = $1 AND table_name = $2",
+                &[&ddb, &name],
+            )
+            .await.map_err(pg_err)?;
+        Ok(row.is_some())
+    }
+
+    async fn table_detail(&mut self, ddb: &str, table: &str) -> Result<Vec<ColumnDetail>> {
+        let (_, name) = Dialect::Postgres.split_table(table);
+        let sql_str = "SELECT
+                c.column_name::text,
+                c.data_type::text,
+                format_type( a.atttypid, a.atttypmod ),
+                c.character_maximum_length::int8,
+                c.numeric_precision::int8,
+                COALESCE( c.numeric_scale, c.datetime_precision )::int8,
+                c.is_nullable = 'YES',
+                c.column_default::text,
+                c.is_identity = 'YES' OR COALESCE( c.column_default, '' ) LIKE 'nextval(%',
+                EXISTS (
+                    SELECT 1 FROM pg_index i
+                    WHERE i.indrelid = a.attrelid AND i.indisprimary AND a.attnum = ANY( i.indkey )
+                ),
+                COALESCE( col_description( a.attrelid, a.attnum ), '' ),
+                c.is_generated = 'ALWAYS'
+            FROM
+                information_schema.columns c
+                INNER JOIN pg_attribute a ON a.attrelid = format( '%I.%I', c.table_schema, c.table_name )::regclass
+                AND a.attname = c.column_name
+            WHERE
+                c.table_schema = $1
+                AND c.table_name = $2
+            ORDER BY
+                c.ordinal_position";
+        let rows = self
+            .client
+            .query(sql_str, &[&ddb, &name])
+            .await
+            .map_err(pg_err)?;
+        let res = rows
+            .iter()
+            .map(|x| ColumnDetail {
+                name: x.get(0),
+                data_type: x.get(1),
+                column_type: x.get(2),
+                max_length: x.get(3),
+                precision: x.get(4),
+                scale: x.get(5),
+                nullable: x.get(6),
+                default: x.get(7),
+                identity: x.get(8),
+                computed: x.get(11),
+                primary_key: x.get(9),
+                comment: x.get(10),
+            })
+            .collect::<Vec<ColumnDetail>>();
+        Ok(res)
+    }
+
+    async fn read_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        filter: &Filter,
+        batch_size: usize,
+        tx: Sender<Batch>,
+    ) -> Result<u64> {
+        let table_name = Dialect::Postgres.table_name(ddb, table);
+        let quoted = columns
+            .iter()
+            .map(|x| Dialect::Postgres.quote(x))
+            .collect::<Vec<String>>();
+        let probe = self
+            .client
+            .prepare(&format!("SELECT {} FROM {}", quoted.join(", "), table_name))
+            .await
+            .map_err(pg_err)?;
+        let select = probe
+            .columns()
+            .iter()
+            .zip(quoted)
+            .map(|(x, quoted)| {
+                if is_native(x.type_()) {
+                    quoted
+                } else {
+                    format!("{}::text", quoted)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let numeric = probe
+            .columns()
+            .iter()
+            .map(|x| *x.type_() == Type::NUMERIC)
+            .collect::<Vec<bool>>();
+
+        let statement = self
+            .client
+            .prepare(&format!(
+                "SELECT {} FROM {}{}",
+                select,
+                table_name,
+                filter.clause()
+            ))
+            .await
+            .map_err(pg_err)?;
+        let stream = self
+            .client
+            .query_raw(&statement, std::iter::empty::<String>())
+            .await
+            .map_err(pg_err)?;
+        pin_mut!(stream);
+
+        let mut read = 0;
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(row) = stream.try_next().await.map_err(pg_err)? {
+            let mut values = Vec::with_capacity(columns.len());
+            for (idx, column) in statement.columns().iter().enumerate() {
+                values.push(match value_at(&row, idx, column.type_())? {
+                    Value::Text(x) if numeric[idx] => Value::Decimal(x),
+                    value => value,
+                });
+            }
+            batch.push(values);
+            if batch.len() >= batch_size {
+                read += batch.len() as u64;
+                tx.send(std::mem::replace(&mut batch, Vec::with_capacity(batch_size)))
+                    .await?;
+            }
+        }
+        if !batch.is_empty() {
+            read += batch.len() as u64;
+            tx.send(batch).await?;
+        }
+        Ok(read)
+    }
+
+    async fn write_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        let table_name = Dialect::Postgres.table_name(ddb, table);
+        let quoted = |columns: &[&String]| {
+            columns
+                .iter()
+                .map(|x| Dialect::Postgres.quote(x))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let column_list = quoted(&columns.iter().collect::<Vec<&String>>());
+        if *on_conflict == OnConflict::Fail {
+            let sql_str = format!("COPY {} ({}) FROM STDIN", table_name, column_list);
+            let sink = self
+                .client
+                .copy_in::<_, Bytes>(sql_str.as_str())
+                .await
+                .map_err(pg_err)?;
+            return send_copy(sink, rows).await;
+        }
+
+        // COPY cannot update or skip existing rows, so they go through a
+        // temporary table dropped again on commit
+        let sent = rows.len() as u64;
+        let updated = on_conflict.updated(columns);
+        let action = if updated.is_empty() {
+            "DO NOTHING".to_string()
+        } else {
+            format!(
+                "DO UPDATE SET {}",
+                updated
+                    .iter()
+                    .map(|x| format!("{0} = EXCLUDED.{0}", Dialect::Postgres.quote(x)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        };
+        let tx = self.client.transaction().await.map_err(pg_err)?;
+        tx.batch_execute(&format!(
+            "CREATE TEMP TABLE {} (LIKE {}) ON COMMIT DROP",
+            STAGE, table_name
+        ))
+        .await
+        .map_err(pg_err)?;
+        let sql_str = format!("COPY {} ({}) FROM STDIN", STAGE, column_list);
+        let sink = tx
+            .copy_in::<_, Bytes>(sql_str.as_str())
+            .await
+            .map_err(pg_err)?;
+        send_copy(sink, rows).await?;
+        tx.batch_execute(&format!(
+            "INSERT INTO {} ({2}) SELECT {2} FROM {1} ON CONFLICT ({3}) {4}",
+            table_name,
+            STAGE,
+            column_list,
+            quoted(&on_conflict.key().iter().collect::<Vec<&String>>()),
+            action
+        ))
+        .await
+        .map_err(pg_err)?;
+        tx.commit().await.map_err(pg_err)?;
+        Ok(sent)
+    }
+
+    /// Types without a native decoding come back as text through the simple
+    /// query protocol.
+    async fn query(&mut self, sql_str: &str) -> Result<Vec<Vec<Value>>> {
+        let statement = self.client.prepare(sql_str).await.map_err(pg_err)?;
+        if !statement.columns().iter().all(|x| is_native(x.type_())) {
+            let rows = self
+                .client
+                .simple_query(sql_str)
+                .await
+                .map_err(pg_err)?
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    SimpleQueryMessage::Row(row) => Some(
+                        (0..row.len())
+                            .map(|idx| match row.get(idx) {
+                                Some(x) => Value::Text(x.to_string()),
+                                None => Value::Null,
+                            })
+                            .collect::<Vec<Value>>(),
+                    ),
+                    _ => None,
+                })
+                .collect::<Vec<Vec<Value>>>();
+            return Ok(rows);
+        }
+
+        let mut rows = vec![];
+        for row in self.client.query(&statement, &[]).await.map_err(pg_err)? {
+            let mut values = Vec::with_capacity(row.len());
+            for (idx, column) in statement.columns().iter().enumerate() {
+                values.push(value_at(&row, idx, column.type_())?);
+            }
+            rows.push(values);
+        }
+        Ok(rows)
+    }
+
+    async fn execute(&mut self, statements: &[String]) -> Result<()> {
+        for sql_str in statements {
+            log_statement(sql_str);
+            self.client.batch_execute(sql_str).await.map_err(pg_err)?;
+        }
+        Ok(())
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rusqlite::{params_from_iter, Connection, OptionalExtension, TransactionBehavior};
+use tokio::sync::mpsc::Sender;
+
+use super::{log_statement, Batch, Driver, ErrorClass, Filter, OnConflict};
+use crate::db::{ColumnDetail, DatabaseConfig, Dialect};
+use crate::error::Result;
+use crate::value::Value;
+
+/// How long a statement waits for other connections to release the file,
+/// such as the writers of other tables or chunks, before failing as busy.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct SqliteDriver {
+    conn: Arc<Mutex<Connection>>,
+    path: String,
+}
+
+impl SqliteDriver {
+    pub async fn connect(db: &DatabaseConfig) -> Result<Self> {
+        let path = db.path.clone();
+        let conn = tokio::task::spawn_blocking(move || open(&path)).await??;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            path: db.path.clone(),
+        })
+    }
+
+    /// Runs `f` on the connection in a blocking thread, since a statement
+    /// may wait up to `BUSY_TIMEOUT` for the file.
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+fn open(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+pub(super) fn error_class(e: &(dyn std::error::Error + 'static)) -> Option<ErrorClass> {
+    match e.downcast_ref::<rusqlite::Error>()?.sqlite_error_code()? {
+        rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => {
+            Some(ErrorClass::LockTimeout)
+        }
+        _ => Some(ErrorClass::Permanent),
+    }
+}
+
+/// Splits a declared type such as `decimal(10,2)` or `int unsigned` into the
+/// bare type name, length, precision and scale.
+fn parse_type(declared: &str) -> (String, Option<i64>, Option<i64>, Option<i64>) {
+    let declared = declared.trim().to_lowercase();
+    let (base, args) = match declared.split_once('(') {
+        Some((base, rest)) => (
+            base.trim(),
+            rest.split(')')
+                .next()
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|x| x.trim().parse::<i64>().ok())
+                .collect::<Vec<i64>>(),
+        ),
+        None => (declared.as_str(), vec![]),
+    };
+    let data_type = base.trim_end_matches(" unsigned").to_string();
+    match (data_type.as_str(), args.as_slice()) {
+        ("datetime" | "timestamp" | "time", [fsp]) => (data_type, None, None, Some(*fsp)),
+        ("decimal" | "numeric", [p]) => (data_type, None, Some(*p), Some(0)),
+        (_, [len]) => (data_type, Some(*len), None, None),
+        (_, [p, s]) => (data_type, None, Some(*p), Some(*s)),
+        _ => (data_type, None, None, None),
+    }
+}
+
+#[async_trait]
+impl Driver for SqliteDriver {
+    /// The main database plus any attached ones.
+    async fn show_dbs(&mut self) -> Result<Vec<String>> {
+        self.blocking(|conn| {
+            let mut stmt = conn.prepare("PRAGMA database_list")?;
+            let res = stmt
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(res)
+        })
+        .await
+    }
+
+    async fn show_tables(&mut self, ddb: &str) -> Result<Vec<String>> {
+        let sql_str = format!(
+            "SELECT name FROM {}.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            Dialect::Sqlite.quote(ddb)
+        );
+        self.blocking(move |conn| {
+            let mut stmt = conn.prepare(&sql_str)?;
+            let res = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+            Ok(res)
+        })
+        .await
+    }
+
+    async fn table_exists(&mut self, ddb: &str, table: &str) -> Result<bool> {
+        let (_, name) = Dialect::Sqlite.split_table(table);
+        let name = name.to_string();
+        let sql_str = format!(
+            "SELECT 1 FROM {}.sqlite_master WHERE type = 'table' AND name = ?1",
+            Dialect::Sqlite.quote(ddb)
+        );
+        self.blocking(move |conn| {
+            let res = conn.query_row(&sql_str, [name], |_| Ok(())).optional()?;
+            Ok(res.is_some())
+        })
+        .await
+    }
+
+    async fn table_detail(&mut self, ddb: &str, table: &str) -> Result<Vec<ColumnDetail>> {
+        let (_, name) = Dialect::Sqlite.split_table(table);
+        let sql_str = format!(
+            "PRAGMA {}.table_info({})",
+            Dialect::Sqlite.quote(ddb),
+            Dialect::Sqlite.quote(name)
+        );
+        let rows = self
+            .blocking(move |conn| {
+                let mut stmt = conn.prepare(&sql_str)?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, bool>(3)?,
+                            row.get::<_, Option<String>>(4)?,
+                            row.get::<_, i64>(5)?,
+                        ))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(rows)
+            })
+            .await?;
+        let keys = rows.iter().filter(|x| x.4 > 0).count();
+        let res = rows
+            .into_iter()
+            .map(|(name, declared, notnull, default, pk)| {
+                let (data_type, max_length, precision, scale) = parse_type(&declared);
+                ColumnDetail {
+                    name,
+                    // a lone INTEGER PRIMARY KEY is an alias of the rowid
+                    identity: pk > 0 && keys == 1 && data_type == "integer",
+                    data_type,
+                    column_type: declared.trim().to_lowercase(),
+                    max_length,
+                    precision,
+                    scale,
+                    nullable: !notnull && pk == 0,
+                    default,
+                    computed: false,
+                    primary_key: pk > 0,
+                    comment: String::new(),
+                }
+            })
+            .collect::<Vec<ColumnDetail>>();
+        Ok(res)
+    }
+
+    /// Reads on a connection of its own in a blocking thread, since sqlite
+    /// statements cannot be held across an await.
+    async fn read_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        filter: &Filter,
+        batch_size: usize,
+        tx: Sender<Batch>,
+    ) -> Result<u64> {
+        let sql_str = format!(
+            "SELECT {} FROM {}{}",
+            columns
+                .iter()
+                .map(|x| Dialect::Sqlite.quote(x))
+                .collect::<Vec<String>>()
+                .join(", "),
+            Dialect::Sqlite.table_name(ddb, table),
+            filter.clause()
+        );
+        let path = self.path.clone();
+        let width = columns.len();
+        tokio::task::spawn_blocking(move || -> Result<u64> {
+            let conn = open(&path)?;
+            let mut stmt = conn.prepare(&sql_str)?;
+            let mut result = stmt.query([])?;
+            let mut read = 0;
+            let mut batch = Vec::with_capacity(batch_size);
+            while let Some(row) = result.next()? {
+                let mut values = Vec::with_capacity(width);
+                for idx in 0..width {
+                    values.push(Value::from(row.get_ref(idx)?));
+                }
+                batch.push(values);
+                if batch.len() >= batch_size {
+                    read += batch.len() as u64;
+                    tx.blocking_send(std::mem::replace(
+                        &mut batch,
+                        Vec::with_capacity(batch_size),
+                    ))?;
+                }
+            }
+            if !batch.is_empty() {
+                read += batch.len() as u64;
+                tx.blocking_send(batch)?;
+            }
+            Ok(read)
+        })
+        .await?
+    }
+
+    /// Writes in one transaction on a connection of its own in a blocking
+    /// thread, like `read_rows`.
+    async fn write_rows(
+        &mut self,
+        ddb: &str,
+        table: &str,
+        columns: &[String],
+        rows: Vec<Vec<Value>>,
+        on_conflict: &OnConflict,
+    ) -> Result<u64> {
+        let quoted = |columns: &[String]| {
+            columns
+                .iter()
+                .map(|x| Dialect::Sqlite.quote(x))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let updated = on_conflict.updated(columns);
+        let tail = match on_conflict {
+            OnConflict::Fail => String::new(),
+            _ if updated.is_empty() => {
+                format!(" ON CONFLICT ({}) DO NOTHING", quoted(on_conflict.key()))
+            }
+            _ => format!(
+                " ON CONFLICT ({}) DO UPDATE SET {}",
+                quoted(on_conflict.key()),
+                updated
+                    .iter()
+                    .map(|x| format!("{0} = excluded.{0}", Dialect::Sqlite.quote(x)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        };
+        let sql_str = format!(
+            "INSERT INTO {} ({}) VALUES ({}){}",
+            Dialect::Sqlite.table_name(ddb, table),
+            quoted(columns),
+            vec!["?"; columns.len()].join(", "),
+            tail
+        );
+        let sent = rows.len() as u64;
+        let fail = *on_conflict == OnConflict::Fail;
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || -> Result<u64> {
+            let mut conn = open(&path)?;
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut count = 0;
+            {
+                let mut stmt = tx.prepare(&sql_str)?;
+                for row in rows {
+                    count += stmt.execute(params_from_iter(row.iter()))? as u64;
+                }
+            }
+            tx.commit()?;
+            Ok(if fail { count } else { sent })
+        })
+        .await?
+    }
+
+    async fn query(&mut self, sql_str: &str) -> Result<Vec<Vec<Value>>> {
+        let sql_str = sql_str.to_string();
+        self.blocking(move |conn| {
+            let mut stmt = conn.prepare(&sql_str)?;
+            let width = stmt.column_count();
+            let mut rows = vec![];
+            let mut result = stmt.query([])?;
+            while let Some(row) = result.next()? {
+                let mut values = Vec::with_capacity(width);
+                for idx in 0..width {
+                    values.push(Value::from(row.get_ref(idx)?));
+                }
+                rows.push(values);
+            }
+            Ok(rows)
+        })
+        .await
+    }
+
+    async fn execute(&mut self, statements: &[String]) -> Result<()> {
+        let statements = statements.to_vec();
+        self.blocking(move |conn| {
+            for sql_str in &statements {
+                log_statement(sql_str);
+                conn.execute_batch(sql_str)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn close(self: Box<Self>) -> Result<()> {
+        if let Ok(conn) = Arc::try_unwrap(self.conn) {
+            let conn = conn.into_inner().unwrap_or_else(|e| e.into_inner());
+            tokio::task::spawn_blocking(move || conn.close().map_err(|(_, e)| e)).await??;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+/// An error from a backend or library, kept whole for its message and for
+/// `driver::classify`.
+pub type Source = Box<dyn std::error::Error + Send + Sync>;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// `conf.ini` cannot be read, or `key` under `[section]` is missing or
+    /// invalid; an empty key stands for the whole section
+    Config {
+        section: String,
+        key: String,
+        message: String,
+    },
+    /// no connection could be made to `target`, e.g. `mysql://host:3306/db`
+    Connect { target: String, source: Source },
+    /// a statement or a row stream failed on an open connection
+    Query(Source),
+    /// a column type or value has no counterpart on the other side
+    Mapping(String),
+    /// progress cannot be saved, or an unfinished copy cannot be resumed
+    Checkpoint(String),
+}
+
+impl Error {
+    pub fn config(section: &str, key: &str, message: impl Into<String>) -> Self {
+        Error::Config {
+            section: section.to_string(),
+            key: key.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// The same failure as seen while connecting to `target`.
+    pub fn connect(self, target: String) -> Self {
+        match self {
+            Error::Query(source) => Error::Connect { target, source },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config {
+                section,
+                key,
+                message,
+            } => match (section.as_str(), key.as_str()) {
+                ("", _) => write!(f, "conf.ini: {}", message),
+                (section, "") => write!(f, "conf.ini [{}]: {}", section, message),
+                (section, key) => write!(f, "conf.ini [{}] {}: {}", section, key, message),
+            },
+            Error::Connect { target, source } => {
+                write!(f, "connect {}: ", target)?;
+                write_chain(f, source.as_ref())
+            }
+            Error::Query(source) => write_chain(f, source.as_ref()),
+            Error::Mapping(message) => write!(f, "{}", message),
+            Error::Checkpoint(message) => write!(f, "checkpoint: {}", message),
+        }
+    }
+}
+
+/// `e` followed by the causes its own message leaves out, as some backends
+/// only say `error connecting to server`.
+fn write_chain(f: &mut fmt::Formatter<'_>, e: &(dyn std::error::Error + 'static)) -> fmt::Result {
+    let mut shown = e.to_string();
+    write!(f, "{}", shown)?;
+    let mut cur = e.source();
+    while let Some(e) = cur {
+        let message = e.to_string();
+        if !shown.contains(&message) {
+            write!(f, ": {}", message)?;
+            shown.push_str(&message);
+        }
+        cur = e.source();
+    }
+    Ok(())
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect { source, .. } | Error::Query(source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Backend errors surfacing through `?` count as query errors; `connect`
+/// turns them into connection errors where they happened while connecting.
+macro_rules! query_error {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl From<$t> for Error {
+                fn from(e: $t) -> Self {
+                    Error::Query(Box::new(e))
+                }
+            }
+        )*
+    };
+}
+
+query_error!(
+    std::io::Error,
+    mysql_async::Error,
+    tiberius::error::Error,
+    tokio_postgres::Error,
+    tokio_postgres::error::DbError,
+    rusqlite::Error,
+    tokio::task::JoinError,
+    tokio::sync::AcquireError,
+    tokio::sync::mpsc::error::SendError<crate::driver::Batch>,
+);
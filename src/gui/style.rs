@@ -5,8 +5,9 @@ use iced::{
 
 // use super::{dark,light};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Theme {
+    #[default]
     Light,
     Dark,
 }
@@ -15,12 +16,6 @@ impl Theme {
     pub const ALL: [Theme; 2] = [Theme::Light, Theme::Dark];
 }
 
-impl Default for Theme {
-    fn default() -> Theme {
-        Theme::Light
-    }
-}
-
 impl std::fmt::Display for Theme {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
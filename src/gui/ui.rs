@@ -1,16 +1,16 @@
 use iced::{
-    button, checkbox, executor,
+    button, executor,
     futures::executor::block_on,
-    pick_list, scrollable,
+    pick_list, scrollable, text_input,
     window::{self, Icon},
-    Align, Application, Button, Checkbox, Clipboard, Column, Command, Container, Element,
-    HorizontalAlignment, Length, PickList, Row, Scrollable, Settings, Subscription, Text,
-    VerticalAlignment,
+    Align, Application, Button, Checkbox, Clipboard, Column, Command, Container, Element, Length,
+    PickList, Row, Scrollable, Settings, Text, TextInput,
 };
 
 use crate::gui::{icon, style};
 
-use crate::db::{DatabaseMeta, Direct};
+use crate::db::{DatabaseMeta, Direct, TableReport, WorkerPool, WriteMode};
+use crate::diff::{self, TableDiff};
 
 pub fn render_window() -> iced::Result {
     let dy_img = image::open("resource/1.ico");
@@ -43,19 +43,15 @@ pub fn render_window() -> iced::Result {
         ..Settings::default()
     })
 }
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Clone, PartialEq, Copy, Default)]
 pub enum Status {
+    #[default]
     LEFT,
     RIGHT,
+    #[allow(dead_code)]
     HIDE,
 }
 
-impl Default for Status {
-    fn default() -> Self {
-        Self::LEFT
-    }
-}
-
 impl Status {
     pub fn toggle(self) -> Self {
         match self {
@@ -71,6 +67,18 @@ struct TableControl {
     name: String,
     index: usize,
     status: Status,
+    /// progress of the last transfer, e.g. `queued` or `120 rows, pass`
+    state: String,
+}
+
+impl TableControl {
+    fn label(&self) -> String {
+        if self.state.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}  [{}]", self.name, self.state)
+        }
+    }
 }
 #[derive(Default, Clone)]
 pub struct MyUi {
@@ -87,7 +95,24 @@ pub struct MyUi {
     init_button: button::State,
     switch_button: button::State,
     ensure_button: button::State,
+    diff_button: button::State,
+    batch_input: text_input::State,
+    batch_size: String,
+    load_data: bool,
+    resume: bool,
+    /// write mode for every table, `None` keeps the modes from conf.ini
+    write_mode: Option<WriteMode>,
+    write_mode_list: pick_list::State<WriteMode>,
+    workers_input: text_input::State,
+    workers: String,
+    /// last error loading conf.ini or the table list
+    error: String,
+    /// differences found by the last diff, one line each
+    diff_lines: Vec<String>,
+    scroll_diff: scrollable::State,
+    #[allow(dead_code)]
     font_dec_button: button::State,
+    #[allow(dead_code)]
     pick_list_theme: pick_list::State<style::Theme>,
     scroll_left: scrollable::State,
     scroll_right: scrollable::State,
@@ -100,9 +125,43 @@ pub enum Message {
 
     SelectedTable((Status, usize)),
     Transfer(Direct),
+    BatchSizeChanged(String),
+    LoadDataToggled(bool),
+    ResumeToggled(bool),
+    WriteModeSelected(WriteMode),
+    WorkersChanged(String),
+    Transferred(String, Result<TableReport, String>),
+    Diff(Direct),
+    Diffed(String, Result<TableDiff, String>),
+    #[allow(dead_code)]
     ThemeChanged(style::Theme),
 }
 
+impl MyUi {
+    /// Tables moved to the pane of the side `direct` copies into.
+    fn selected_tables(&self, direct: Direct) -> Vec<String> {
+        self.table_list
+            .iter()
+            .filter(|x| {
+                x.status
+                    == match direct {
+                        Direct::FROM => Status::RIGHT,
+                        Direct::TO => Status::LEFT,
+                    }
+            })
+            .map(|x| x.name.clone())
+            .collect::<Vec<String>>()
+    }
+
+    /// Source and target database of a transfer in `direct`.
+    fn db_names(&self, direct: Direct) -> (String, String) {
+        match direct {
+            Direct::FROM => (self.db_name.clone(), self.db_name_to.clone()),
+            Direct::TO => (self.db_name_to.clone(), self.db_name.clone()),
+        }
+    }
+}
+
 impl Application for MyUi {
     type Executor = executor::Default;
     type Message = Message;
@@ -121,7 +180,7 @@ impl Application for MyUi {
         String::from("SQL")
     }
 
-    fn view(&mut self) -> Element<Message> {
+    fn view(&mut self) -> Element<'_, Message> {
         let init_button = Button::new(
             &mut self.init_button,
             Text::new("load conf").height(Length::Shrink),
@@ -136,7 +195,7 @@ impl Application for MyUi {
             .zip(&mut self.check_button_list_left)
             .fold(Column::new().spacing(1), |col, (table, but)| {
                 col.push(
-                    Button::new(but, Text::new(table.name.to_owned()).height(Length::Fill))
+                    Button::new(but, Text::new(table.label()).height(Length::Fill))
                         .style(self.theme.unwrap())
                         .on_press(Message::SelectedTable((table.status, table.index))),
                 )
@@ -149,7 +208,7 @@ impl Application for MyUi {
             .zip(&mut self.check_button_list_right)
             .fold(Column::new().spacing(1), |col, (table, but)| {
                 col.push(
-                    Button::new(but, Text::new(table.name.to_owned()).height(Length::Fill))
+                    Button::new(but, Text::new(table.label()).height(Length::Fill))
                         .style(self.theme.unwrap())
                         .on_press(Message::SelectedTable((table.status, table.index))),
                 )
@@ -176,6 +235,47 @@ impl Application for MyUi {
                 .width(Length::Shrink)
                 .height(Length::Shrink),
             )
+            .push(
+                TextInput::new(
+                    &mut self.batch_input,
+                    "batch size",
+                    &self.batch_size,
+                    Message::BatchSizeChanged,
+                )
+                .style(self.theme.unwrap())
+                .width(Length::Units(80))
+                .padding(2),
+            )
+            .push(
+                TextInput::new(
+                    &mut self.workers_input,
+                    "workers",
+                    &self.workers,
+                    Message::WorkersChanged,
+                )
+                .style(self.theme.unwrap())
+                .width(Length::Units(80))
+                .padding(2),
+            )
+            .push(
+                Checkbox::new(self.load_data, "load data", Message::LoadDataToggled)
+                    .style(self.theme.unwrap()),
+            )
+            .push(
+                Checkbox::new(self.resume, "resume", Message::ResumeToggled)
+                    .style(self.theme.unwrap()),
+            )
+            .push(
+                PickList::new(
+                    &mut self.write_mode_list,
+                    &WriteMode::ALL[..],
+                    self.write_mode,
+                    Message::WriteModeSelected,
+                )
+                .style(self.theme.unwrap())
+                .width(Length::Units(110))
+                .padding(2),
+            )
             .push(
                 Button::new(
                     &mut self.ensure_button,
@@ -186,6 +286,16 @@ impl Application for MyUi {
                 .width(Length::Shrink)
                 .height(Length::Shrink),
             )
+            .push(
+                Button::new(
+                    &mut self.diff_button,
+                    Text::new("diff").height(Length::Fill),
+                )
+                .style(self.theme.unwrap())
+                .on_press(Message::Diff(self.direction))
+                .width(Length::Shrink)
+                .height(Length::Shrink),
+            )
             .align_items(Align::Center);
 
         let row = Row::new()
@@ -225,11 +335,30 @@ impl Application for MyUi {
             )
             .padding(5);
 
-        let content = Column::new()
+        let mut content = Column::new()
             // .padding(5)
             // .align_items(Alignment::c)
             .push(init_button)
+            .push(Text::new(&self.error).color(iced::Color::from_rgb(0.8, 0.0, 0.0)))
             .push(row);
+        if !self.diff_lines.is_empty() {
+            let lines = self
+                .diff_lines
+                .iter()
+                .fold(Column::new().spacing(1), |col, line| {
+                    col.push(Text::new(line).size(16))
+                });
+            content = content.push(
+                Container::new(
+                    Scrollable::new(&mut self.scroll_diff)
+                        .push(lines)
+                        .width(Length::Fill)
+                        .height(Length::Units(150)),
+                )
+                .style(self.theme.unwrap())
+                .padding(5),
+            );
+        }
 
         // .push(button2);
 
@@ -243,9 +372,16 @@ impl Application for MyUi {
 
     fn update(&mut self, message: Self::Message, _: &mut Clipboard) -> Command<Self::Message> {
         match message {
-            Message::LoadConf(mut direct) => {
+            Message::LoadConf(direct) => {
                 println!("{:?}", direct);
-                let db_meta = DatabaseMeta::initial();
+                let db_meta = match DatabaseMeta::initial() {
+                    Ok(db_meta) => db_meta,
+                    Err(e) => {
+                        self.error = e.to_string();
+                        return Command::none();
+                    }
+                };
+                self.error.clear();
                 self.db_meta = db_meta.clone();
                 match direct {
                     Direct::FROM => {
@@ -265,6 +401,7 @@ impl Application for MyUi {
                         .await
                 });
 
+                let (from_name, to_name) = self.db_names(direct);
                 match table_list {
                     Ok(val) => {
                         self.table_list = val
@@ -277,21 +414,66 @@ impl Application for MyUi {
                                     Direct::FROM => Status::LEFT,
                                     Direct::TO => Status::RIGHT,
                                 },
+                                // offer to pick up copies a crash or closed window cut short
+                                state: if self
+                                    .db_meta
+                                    .has_checkpoint(direct, &from_name, &to_name, x)
+                                {
+                                    "unfinished".to_string()
+                                } else if let Some(mark) =
+                                    self.db_meta.watermark(direct, &from_name, &to_name, x)
+                                {
+                                    format!("up to {}", mark.text)
+                                } else {
+                                    String::new()
+                                },
                             })
                             .collect::<Vec<TableControl>>();
+                        self.resume = self.table_list.iter().any(|x| x.state == "unfinished");
                         self.check_button_list_left = vec![button::State::new(); val.len()];
                         self.check_button_list_right = vec![button::State::new(); val.len()];
                     }
-                    Err(_) => {
-                        self.table_list = vec![TableControl {
-                            name: "TIMEOUT".to_string(),
-                            index: 0,
-                            status: Status::LEFT,
-                        }]
+                    Err(e) => {
+                        self.table_list = vec![];
+                        self.error = e.to_string();
                     }
                 }
             }
             Message::ThemeChanged(t) => self.theme = Some(t),
+            Message::BatchSizeChanged(size) => {
+                if size.chars().all(|c| c.is_ascii_digit()) {
+                    self.batch_size = size;
+                }
+            }
+            Message::LoadDataToggled(on) => self.load_data = on,
+            Message::ResumeToggled(on) => self.resume = on,
+            Message::WriteModeSelected(mode) => self.write_mode = Some(mode),
+            Message::WorkersChanged(workers) => {
+                if workers.chars().all(|c| c.is_ascii_digit()) {
+                    self.workers = workers;
+                }
+            }
+            Message::Transferred(table, res) => {
+                let state = match res {
+                    Ok(report) => {
+                        let mut state = format!("{} rows", report.rows);
+                        if let Some(badge) = report.badge() {
+                            state.push_str(&format!(", {}", badge));
+                        }
+                        if let Some(watermark) = &report.watermark {
+                            state.push_str(&format!(", up to {}", watermark));
+                        }
+                        if let Some(deleted) = report.deleted {
+                            state.push_str(&format!(", {} deleted", deleted));
+                        }
+                        state
+                    }
+                    Err(e) => format!("failed: {}", e),
+                };
+                if let Some(x) = self.table_list.iter_mut().find(|x| x.name == table) {
+                    x.state = state;
+                }
+            }
             Message::SelectedTable(table) => {
                 println!("{:?}", table);
 
@@ -302,36 +484,89 @@ impl Application for MyUi {
                 //     self.table_list[each.to_owned()].1 =b
                 // }
             }
+            Message::Diff(direct) => {
+                let res = self.selected_tables(direct);
+                let (from_name, to_name) = self.db_names(direct);
+                self.diff_lines.clear();
+                let mut commands = vec![];
+                for table in res {
+                    if let Some(x) = self.table_list.iter_mut().find(|x| x.name == table) {
+                        x.state = "comparing".to_string();
+                    }
+                    let job = diff::diff_table(
+                        self.db_meta.clone(),
+                        direct,
+                        from_name.clone(),
+                        to_name.clone(),
+                        table.clone(),
+                        vec![],
+                        100,
+                    );
+                    commands.push(Command::perform(
+                        async move { job.await.map_err(|e| e.to_string()) },
+                        move |res| Message::Diffed(table.clone(), res),
+                    ));
+                }
+                return Command::batch(commands);
+            }
+            Message::Diffed(table, res) => {
+                let state = match res {
+                    Ok(table_diff) if table_diff.is_empty() => "in sync".to_string(),
+                    Ok(table_diff) => {
+                        self.diff_lines
+                            .push(format!("{}: {}", table, table_diff.summary()));
+                        for row in &table_diff.rows {
+                            self.diff_lines
+                                .push(format!("    {}", table_diff.describe(row)));
+                        }
+                        table_diff.summary()
+                    }
+                    Err(e) => format!("diff failed: {}", e),
+                };
+                if let Some(x) = self.table_list.iter_mut().find(|x| x.name == table) {
+                    x.state = state;
+                }
+            }
             Message::Transfer(direct) => {
-                // self.db_meta.table_detail(direct, ddb, table)
-                let res = self
-                    .table_list
-                    .iter()
-                    .filter(|x| {
-                        x.status
-                            == match direct {
-                                Direct::FROM => Status::RIGHT,
-                                Direct::TO => Status::LEFT,
-                            }
-                    })
-                    .map(|x| x.name.clone())
-                    .collect::<Vec<String>>();
+                let res = self.selected_tables(direct);
 
                 println!("{:?}", res);
 
-                let name = match direct {
-                    Direct::FROM => self.db_name.clone(),
-                    Direct::TO => self.db_name_to.clone(),
-                };
+                let (from_name, to_name) = self.db_names(direct);
 
-                let table_detail = block_on(async move {
-                    self.db_meta
-                        .clone()
-                        .table_detail(self.direction, name, res[0].clone())
-                        .await
-                });
+                let mut meta = self.db_meta.clone();
+                meta.set_load_data(self.load_data);
+                meta.set_resume(self.resume);
+                if let Some(mode) = self.write_mode {
+                    meta.set_write_mode(mode);
+                }
+                // an empty box keeps the sizes from conf.ini
+                if let Ok(size @ 1..) = self.batch_size.parse::<usize>() {
+                    meta.set_batch_size(size);
+                }
+                if let Ok(workers @ 1..) = self.workers.parse::<usize>() {
+                    meta.set_workers(workers);
+                }
 
-                println!("{:?}", table_detail);
+                let pool = WorkerPool::new(meta.workers());
+                let mut commands = vec![];
+                for table in res {
+                    if let Some(x) = self.table_list.iter_mut().find(|x| x.name == table) {
+                        x.state = "queued".to_string();
+                    }
+                    let job = pool.clone().transfer_table(
+                        meta.clone(),
+                        direct,
+                        from_name.clone(),
+                        to_name.clone(),
+                        table.clone(),
+                    );
+                    commands.push(Command::perform(
+                        async move { job.await.map_err(|e| e.to_string()) },
+                        move |res| Message::Transferred(table.clone(), res),
+                    ));
+                }
+                return Command::batch(commands);
             }
             Message::DirectChanged => {
                 println!("222{:?}", self.direction);
@@ -1,18 +1,28 @@
+#![allow(clippy::upper_case_acronyms)]
 use gui::ui::render_window;
-use mysql_async::{
-    prelude::{FromRow, FromValue, Queryable},
-    Conn, Opts, Pool,
-};
-use tiberius::{AuthMethod, Client, Config, Row};
-use tokio::net::TcpStream;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
 
+mod binlog;
+mod cdc;
+mod checkpoint;
+mod cli;
 mod db;
+mod ddl;
+mod diff;
+mod driver;
+mod error;
 mod gui;
+mod value;
+mod watermark;
 
 #[tokio::main]
-async fn main()  {
-    render_window();
-
-    
+async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.is_empty() {
+        if let Err(e) = render_window() {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    std::process::exit(cli::run(args).await);
 }
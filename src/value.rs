@@ -0,0 +1,455 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, SubsecRound, Timelike};
+use mysql_async::consts::ColumnType;
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use tiberius::{ColumnData, FromSql, ToSql};
+
+/// A single cell, independent of the backend it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+    Bytes(Vec<u8>),
+    /// exact numeric kept as its textual form so no precision is lost
+    Decimal(String),
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+    Time(NaiveTime),
+}
+
+impl Value {
+    /// Converts a cell read over the mysql binary protocol, using the column to
+    /// tell text, binary and date values apart.
+    pub fn from_mysql(value: mysql_async::Value, column: &mysql_async::Column) -> Self {
+        match value {
+            mysql_async::Value::NULL => Value::Null,
+            mysql_async::Value::Int(x) => Value::Int(x),
+            mysql_async::Value::UInt(x) => Value::UInt(x),
+            mysql_async::Value::Float(x) => Value::Float(x as f64),
+            mysql_async::Value::Double(x) => Value::Float(x),
+            mysql_async::Value::Bytes(b) => match column.column_type() {
+                ColumnType::MYSQL_TYPE_DECIMAL | ColumnType::MYSQL_TYPE_NEWDECIMAL => {
+                    Value::Decimal(String::from_utf8_lossy(&b).into_owned())
+                }
+                // JSON is reported with the binary character set but is utf8 text
+                ColumnType::MYSQL_TYPE_JSON => {
+                    Value::Text(String::from_utf8_lossy(&b).into_owned())
+                }
+                // character set 63 is `binary`
+                _ if column.character_set() == 63 => Value::Bytes(b),
+                _ => match String::from_utf8(b) {
+                    Ok(s) => Value::Text(s),
+                    Err(e) => Value::Bytes(e.into_bytes()),
+                },
+            },
+            mysql_async::Value::Date(y, m, d, h, mi, s, us) => {
+                let date = NaiveDate::from_ymd_opt(y as i32, m as u32, d as u32);
+                let time = NaiveTime::from_hms_micro_opt(h as u32, mi as u32, s as u32, us);
+                match (column.column_type(), date, time) {
+                    (ColumnType::MYSQL_TYPE_DATE, Some(date), _) => Value::Date(date),
+                    (_, Some(date), Some(time)) => Value::DateTime(NaiveDateTime::new(date, time)),
+                    // zero dates such as 0000-00-00 have no chrono equivalent
+                    _ => Value::Null,
+                }
+            }
+            mysql_async::Value::Time(neg, days, h, mi, s, us) => {
+                match NaiveTime::from_hms_micro_opt(h as u32, mi as u32, s as u32, us) {
+                    Some(time) if !neg && days == 0 => Value::Time(time),
+                    _ => Value::Text(format!(
+                        "{}{}:{:02}:{:02}.{:06}",
+                        if neg { "-" } else { "" },
+                        days * 24 + h as u32,
+                        mi,
+                        s,
+                        us
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Appends the value as one field of PostgreSQL's text `COPY` format.
+    pub fn write_copy(&self, buf: &mut String) {
+        match self {
+            Value::Null => buf.push_str("\\N"),
+            Value::Int(x) => buf.push_str(&x.to_string()),
+            Value::UInt(x) => buf.push_str(&x.to_string()),
+            Value::Float(x) if x.is_nan() => buf.push_str("NaN"),
+            Value::Float(x) if x.is_infinite() => {
+                buf.push_str(if *x > 0.0 { "Infinity" } else { "-Infinity" })
+            }
+            Value::Float(x) => buf.push_str(&x.to_string()),
+            Value::Bool(x) => buf.push(if *x { 't' } else { 'f' }),
+            Value::Text(x) | Value::Decimal(x) => {
+                for c in x.chars() {
+                    match c {
+                        '\\' => buf.push_str("\\\\"),
+                        '\t' => buf.push_str("\\t"),
+                        '\n' => buf.push_str("\\n"),
+                        '\r' => buf.push_str("\\r"),
+                        _ => buf.push(c),
+                    }
+                }
+            }
+            // bytea hex input, with the backslash itself escaped for COPY
+            Value::Bytes(x) => {
+                buf.push_str("\\\\x");
+                for b in x {
+                    buf.push_str(&format!("{:02x}", b));
+                }
+            }
+            Value::Date(x) => buf.push_str(&x.format("%Y-%m-%d").to_string()),
+            Value::DateTime(x) => buf.push_str(&x.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+            Value::Time(x) => buf.push_str(&x.format("%H:%M:%S%.f").to_string()),
+        }
+    }
+
+    /// Text form that reads the same whichever backend the value came back
+    /// from, for comparing a copy with its source: numbers without redundant
+    /// zeros, dates and times in ISO-8601 and bytes in hex. `None` for null.
+    pub fn canonical(&self) -> Option<String> {
+        let text = match self {
+            Value::Null => return None,
+            Value::Int(x) => x.to_string(),
+            Value::UInt(x) => x.to_string(),
+            Value::Float(x) => x.to_string(),
+            Value::Bool(x) => (*x as u8).to_string(),
+            Value::Decimal(x) => trim_number(x),
+            // sqlite keeps dates as text, postgres hands some types back as text
+            Value::Text(x) => {
+                if is_number(x) {
+                    trim_number(x)
+                } else if let Ok(x) = NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f") {
+                    return Value::DateTime(x).canonical();
+                } else if let Ok(x) = NaiveDate::parse_from_str(x, "%Y-%m-%d") {
+                    return Value::Date(x).canonical();
+                } else {
+                    x.clone()
+                }
+            }
+            Value::Bytes(x) => x.iter().map(|b| format!("{:02x}", b)).collect(),
+            Value::Date(x) => x.format("%Y-%m-%d").to_string(),
+            Value::DateTime(x) => trim_fraction(x.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+            Value::Time(x) => trim_fraction(x.format("%H:%M:%S%.f").to_string()),
+        };
+        Some(text)
+    }
+
+    /// The value with fractional seconds rounded to `digits`, as a column
+    /// keeping that many stores it. Other values are returned unchanged.
+    pub fn round_seconds(&self, digits: u16) -> Value {
+        match self {
+            Value::DateTime(x) => Value::DateTime(x.round_subsecs(digits)),
+            Value::Time(x) => Value::Time(x.round_subsecs(digits)),
+            Value::Text(x) => match NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f") {
+                Ok(x) => Value::DateTime(x.round_subsecs(digits)),
+                Err(_) => self.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Appends the value as one field of MySQL's default `LOAD DATA` format,
+    /// tab separated with backslash escapes.
+    pub fn write_tsv(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Null => buf.extend_from_slice(b"\\N"),
+            Value::Int(x) => buf.extend_from_slice(x.to_string().as_bytes()),
+            Value::UInt(x) => buf.extend_from_slice(x.to_string().as_bytes()),
+            Value::Float(x) => buf.extend_from_slice(x.to_string().as_bytes()),
+            Value::Bool(x) => buf.push(if *x { b'1' } else { b'0' }),
+            Value::Text(x) | Value::Decimal(x) => escape_tsv(x.as_bytes(), buf),
+            Value::Bytes(x) => escape_tsv(x, buf),
+            Value::Date(x) => buf.extend_from_slice(x.format("%Y-%m-%d").to_string().as_bytes()),
+            Value::DateTime(x) => buf.extend_from_slice(
+                x.format("%Y-%m-%d %H:%M:%S%.6f").to_string().as_bytes(),
+            ),
+            Value::Time(x) => {
+                buf.extend_from_slice(x.format("%H:%M:%S%.6f").to_string().as_bytes())
+            }
+        }
+    }
+}
+
+pub fn is_number(x: &str) -> bool {
+    x.chars().any(|c| c.is_ascii_digit())
+        && x.chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.'))
+        && x.parse::<f64>().is_ok()
+}
+
+/// `x` without a plus sign, leading zeros or zeros after the point.
+fn trim_number(x: &str) -> String {
+    let x = x.trim().trim_start_matches('+');
+    let (sign, x) = match x.strip_prefix('-') {
+        Some(x) => ("-", x),
+        None => ("", x),
+    };
+    let mut x = x.trim_start_matches('0');
+    if x.contains('.') {
+        x = x.trim_end_matches('0').trim_end_matches('.');
+    }
+    match x {
+        "" => "0".to_string(),
+        x if x.starts_with('.') => format!("{}0{}", sign, x),
+        x => format!("{}{}", sign, x),
+    }
+}
+
+fn trim_fraction(x: String) -> String {
+    if x.contains('.') {
+        x.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        x
+    }
+}
+
+fn escape_tsv(data: &[u8], buf: &mut Vec<u8>) {
+    for b in data {
+        match b {
+            b'\\' => buf.extend_from_slice(b"\\\\"),
+            b'\t' => buf.extend_from_slice(b"\\t"),
+            b'\n' => buf.extend_from_slice(b"\\n"),
+            b'\r' => buf.extend_from_slice(b"\\r"),
+            0 => buf.extend_from_slice(b"\\0"),
+            _ => buf.push(*b),
+        }
+    }
+}
+
+impl From<Value> for mysql_async::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => mysql_async::Value::NULL,
+            Value::Int(x) => mysql_async::Value::Int(x),
+            Value::UInt(x) => mysql_async::Value::UInt(x),
+            Value::Float(x) => mysql_async::Value::Double(x),
+            Value::Bool(x) => mysql_async::Value::Int(x as i64),
+            Value::Text(x) | Value::Decimal(x) => mysql_async::Value::Bytes(x.into_bytes()),
+            Value::Bytes(x) => mysql_async::Value::Bytes(x),
+            Value::Date(d) => {
+                mysql_async::Value::Date(d.year() as u16, d.month() as u8, d.day() as u8, 0, 0, 0, 0)
+            }
+            Value::DateTime(dt) => mysql_async::Value::Date(
+                dt.year() as u16,
+                dt.month() as u8,
+                dt.day() as u8,
+                dt.hour() as u8,
+                dt.minute() as u8,
+                dt.second() as u8,
+                dt.nanosecond() / 1000,
+            ),
+            Value::Time(t) => mysql_async::Value::Time(
+                false,
+                0,
+                t.hour() as u8,
+                t.minute() as u8,
+                t.second() as u8,
+                t.nanosecond() / 1000,
+            ),
+        }
+    }
+}
+
+impl From<ColumnData<'static>> for Value {
+    fn from(data: ColumnData<'static>) -> Self {
+        let value = match &data {
+            ColumnData::U8(x) => x.map(|x| Value::Int(x as i64)),
+            ColumnData::I16(x) => x.map(|x| Value::Int(x as i64)),
+            ColumnData::I32(x) => x.map(|x| Value::Int(x as i64)),
+            ColumnData::I64(x) => x.map(Value::Int),
+            ColumnData::F32(x) => x.map(|x| Value::Float(x as f64)),
+            ColumnData::F64(x) => x.map(Value::Float),
+            ColumnData::Bit(x) => x.map(Value::Bool),
+            ColumnData::String(x) => x.as_ref().map(|x| Value::Text(x.to_string())),
+            ColumnData::Guid(x) => x.map(|x| Value::Text(x.to_string().to_uppercase())),
+            ColumnData::Binary(x) => x.as_ref().map(|x| Value::Bytes(x.to_vec())),
+            ColumnData::Numeric(x) => x.map(|x| Value::Decimal(x.to_string())),
+            ColumnData::Xml(x) => x.as_ref().map(|x| Value::Text(x.to_string())),
+            ColumnData::DateTime(_) | ColumnData::SmallDateTime(_) | ColumnData::DateTime2(_) => {
+                NaiveDateTime::from_sql(&data).ok().flatten().map(Value::DateTime)
+            }
+            ColumnData::Date(_) => NaiveDate::from_sql(&data).ok().flatten().map(Value::Date),
+            ColumnData::Time(_) => NaiveTime::from_sql(&data).ok().flatten().map(Value::Time),
+            ColumnData::DateTimeOffset(_) => {
+                chrono::DateTime::<chrono::Utc>::from_sql(&data)
+                    .ok()
+                    .flatten()
+                    .map(|x| Value::DateTime(x.naive_utc()))
+            }
+        };
+        value.unwrap_or(Value::Null)
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> ColumnData<'_> {
+        match self {
+            Value::Null => ColumnData::String(None),
+            Value::Int(x) => ColumnData::I64(Some(*x)),
+            Value::UInt(x) => match i64::try_from(*x) {
+                Ok(x) => ColumnData::I64(Some(x)),
+                Err(_) => ColumnData::String(Some(x.to_string().into())),
+            },
+            Value::Float(x) => ColumnData::F64(Some(*x)),
+            Value::Bool(x) => ColumnData::Bit(Some(*x)),
+            Value::Text(x) | Value::Decimal(x) => ColumnData::String(Some(x.as_str().into())),
+            Value::Bytes(x) => ColumnData::Binary(Some(x.as_slice().into())),
+            Value::Date(x) => x.to_sql(),
+            Value::DateTime(x) => x.to_sql(),
+            Value::Time(x) => x.to_sql(),
+        }
+    }
+}
+
+impl From<ValueRef<'_>> for Value {
+    fn from(value: ValueRef<'_>) -> Self {
+        match value {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(x) => Value::Int(x),
+            ValueRef::Real(x) => Value::Float(x),
+            ValueRef::Text(x) => match String::from_utf8(x.to_vec()) {
+                Ok(s) => Value::Text(s),
+                Err(e) => Value::Bytes(e.into_bytes()),
+            },
+            ValueRef::Blob(x) => Value::Bytes(x.to_vec()),
+        }
+    }
+}
+
+/// Dates and times are stored as ISO-8601 text, which SQLite's date
+/// functions understand.
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            Value::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+            Value::Int(x) => ToSqlOutput::from(*x),
+            Value::UInt(x) => match i64::try_from(*x) {
+                Ok(x) => ToSqlOutput::from(x),
+                Err(_) => ToSqlOutput::from(x.to_string()),
+            },
+            Value::Float(x) => ToSqlOutput::from(*x),
+            Value::Bool(x) => ToSqlOutput::from(*x),
+            Value::Text(x) | Value::Decimal(x) => ToSqlOutput::from(x.as_str()),
+            Value::Bytes(x) => ToSqlOutput::from(x.as_slice()),
+            Value::Date(x) => ToSqlOutput::from(x.format("%Y-%m-%d").to_string()),
+            Value::DateTime(x) => {
+                ToSqlOutput::from(x.format("%Y-%m-%d %H:%M:%S%.f").to_string())
+            }
+            Value::Time(x) => ToSqlOutput::from(x.format("%H:%M:%S%.f").to_string()),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy(value: Value) -> String {
+        let mut buf = String::new();
+        value.write_copy(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn write_copy_fields() {
+        assert_eq!(copy(Value::Null), "\\N");
+        assert_eq!(copy(Value::Int(-3)), "-3");
+        assert_eq!(copy(Value::Bool(true)), "t");
+        assert_eq!(copy(Value::Float(f64::NAN)), "NaN");
+        assert_eq!(copy(Value::Float(f64::NEG_INFINITY)), "-Infinity");
+        assert_eq!(copy(Value::Text("a\tb\\c\n".to_string())), "a\\tb\\\\c\\n");
+        assert_eq!(copy(Value::Bytes(vec![0, 0xab])), "\\\\x00ab");
+        let dt = NaiveDate::from_ymd_opt(2024, 2, 29)
+            .unwrap()
+            .and_hms_micro_opt(1, 2, 3, 500)
+            .unwrap();
+        assert_eq!(copy(Value::DateTime(dt)), "2024-02-29 01:02:03.000500");
+    }
+
+    fn tsv(data: &[u8]) -> Vec<u8> {
+        let mut buf = vec![];
+        escape_tsv(data, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn escape_tsv_escapes_separators() {
+        assert_eq!(tsv(b"plain"), b"plain");
+        assert_eq!(tsv(b"a\tb\nc\rd"), b"a\\tb\\nc\\rd");
+        assert_eq!(tsv(b"back\\slash"), b"back\\\\slash");
+        assert_eq!(tsv(b"nul\0byte"), b"nul\\0byte");
+    }
+
+    #[test]
+    fn write_tsv_marks_null() {
+        let mut buf = vec![];
+        Value::Null.write_tsv(&mut buf);
+        buf.push(b'\t');
+        Value::Text("N".to_string()).write_tsv(&mut buf);
+        assert_eq!(buf, b"\\N\tN");
+    }
+
+    #[test]
+    fn trim_number_drops_redundant_zeros() {
+        assert_eq!(trim_number("0012.3400"), "12.34");
+        assert_eq!(trim_number("+5"), "5");
+        assert_eq!(trim_number("-0.50"), "-0.5");
+        assert_eq!(trim_number("100"), "100");
+        assert_eq!(trim_number("10.000"), "10");
+        assert_eq!(trim_number("0.000"), "0");
+        assert_eq!(trim_number(" 000 "), "0");
+    }
+
+    #[test]
+    fn canonical_matches_across_backends() {
+        assert_eq!(
+            Value::Decimal("1.50".to_string()).canonical(),
+            Value::Text("1.5".to_string()).canonical()
+        );
+        assert_eq!(
+            Value::Text("2024-01-02 03:04:05.000".to_string()).canonical(),
+            Some("2024-01-02 03:04:05".to_string())
+        );
+        assert_eq!(Value::Bool(true).canonical(), Value::Int(1).canonical());
+        assert_eq!(Value::Null.canonical(), None);
+    }
+
+    #[test]
+    fn round_seconds_to_column_scale() {
+        // MSSQL datetime keeps steps of 1/300 second
+        let dt = NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_nano_opt(3, 4, 5, 123_333_333)
+            .unwrap();
+        assert_eq!(
+            Value::DateTime(dt).round_seconds(3).canonical(),
+            Some("2024-01-02 03:04:05.123".to_string())
+        );
+        assert_eq!(
+            Value::Text("2024-01-02 03:04:05.9996".to_string())
+                .round_seconds(3)
+                .canonical(),
+            Some("2024-01-02 03:04:06".to_string())
+        );
+        assert_eq!(Value::Int(7).round_seconds(0), Value::Int(7));
+    }
+
+    #[test]
+    fn mysql_json_is_text() {
+        let column = |kind| mysql_async::Column::new(kind).with_character_set(63);
+        let json = mysql_async::Value::Bytes(br#"{"a": 1}"#.to_vec());
+        assert_eq!(
+            Value::from_mysql(json.clone(), &column(ColumnType::MYSQL_TYPE_JSON)),
+            Value::Text(r#"{"a": 1}"#.to_string())
+        );
+        assert_eq!(
+            Value::from_mysql(json, &column(ColumnType::MYSQL_TYPE_BLOB)),
+            Value::Bytes(br#"{"a": 1}"#.to_vec())
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use ini::Ini;
+
+use crate::db::Dialect;
+use crate::error::{Error, Result};
+use crate::value::{is_number, Value};
+
+/// Watermarks of incrementally copied tables, kept next to `conf.ini`.
+const FILE: &str = "watermark.ini";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarkKind {
+    Number,
+    Date,
+    DateTime,
+    /// MSSQL `rowversion` and other binary counters, kept in hex
+    Binary,
+    Text,
+}
+
+impl MarkKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MarkKind::Number => "number",
+            MarkKind::Date => "date",
+            MarkKind::DateTime => "datetime",
+            MarkKind::Binary => "binary",
+            MarkKind::Text => "text",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "number" => Some(MarkKind::Number),
+            "date" => Some(MarkKind::Date),
+            "datetime" => Some(MarkKind::DateTime),
+            "binary" => Some(MarkKind::Binary),
+            "text" => Some(MarkKind::Text),
+            _ => None,
+        }
+    }
+}
+
+/// The highest value of a watermark column copied so far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mark {
+    pub kind: MarkKind,
+    pub text: String,
+}
+
+impl Mark {
+    /// `None` for null, as an empty table has no watermark yet.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let kind = match value {
+            Value::Null => return None,
+            Value::Int(_) | Value::UInt(_) | Value::Float(_) | Value::Decimal(_) => {
+                MarkKind::Number
+            }
+            Value::Date(_) => MarkKind::Date,
+            Value::DateTime(_) => MarkKind::DateTime,
+            Value::Bytes(_) => MarkKind::Binary,
+            // sqlite keeps dates as text, other backends may hand numbers back as text
+            Value::Text(x) => {
+                if is_number(x) {
+                    MarkKind::Number
+                } else if NaiveDateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f").is_ok() {
+                    MarkKind::DateTime
+                } else if NaiveDate::parse_from_str(x, "%Y-%m-%d").is_ok() {
+                    MarkKind::Date
+                } else {
+                    MarkKind::Text
+                }
+            }
+            Value::Bool(_) | Value::Time(_) => MarkKind::Text,
+        };
+        Some(Self {
+            kind,
+            text: value.canonical()?,
+        })
+    }
+
+    /// The mark as a literal of `dialect`, to compare the column with.
+    pub fn literal(&self, dialect: Dialect) -> String {
+        match (self.kind, dialect) {
+            (MarkKind::Number, _) => self.text.clone(),
+            // MSSQL datetime2 takes at most seven digits after the point
+            (MarkKind::DateTime, Dialect::Mssql) => {
+                match NaiveDateTime::parse_from_str(&self.text, "%Y-%m-%d %H:%M:%S%.f") {
+                    Ok(x) => format!(
+                        "CAST({} AS datetime2)",
+                        dialect.literal(&format!(
+                            "{}.{:07}",
+                            x.format("%Y-%m-%dT%H:%M:%S"),
+                            x.and_utc().timestamp_subsec_nanos() / 100
+                        ))
+                    ),
+                    Err(_) => dialect.literal(&self.text),
+                }
+            }
+            (MarkKind::Binary, Dialect::Mssql) => format!("0x{}", self.text),
+            (MarkKind::Binary, Dialect::Postgres) => format!("'\\x{}'::bytea", self.text),
+            (MarkKind::Binary, Dialect::Mysql | Dialect::Sqlite) => format!("X'{}'", self.text),
+            _ => dialect.literal(&self.text),
+        }
+    }
+}
+
+/// Watermark of one table as copied from one source to one target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub column: String,
+    /// highest value copied by the last finished run
+    pub value: Option<Mark>,
+    /// highest value the running copy goes up to, kept so a resumed copy
+    /// reads the same rows
+    pub pending: Option<Mark>,
+}
+
+impl Watermark {
+    fn parse(inf: &ini::Properties) -> Option<Self> {
+        let mark = |name: &str| {
+            Some(Mark {
+                kind: MarkKind::parse(inf.get(format!("{}.kind", name))?)?,
+                text: inf.get(name)?.to_string(),
+            })
+        };
+        Some(Self {
+            column: inf.get("column")?.to_string(),
+            value: mark("value"),
+            pending: mark("pending"),
+        })
+    }
+
+    fn write(&self, conf: &mut Ini, id: &str) {
+        let mut entries = vec![("column".to_string(), self.column.clone())];
+        for (name, mark) in [("value", &self.value), ("pending", &self.pending)] {
+            if let Some(mark) = mark {
+                entries.push((name.to_string(), mark.text.clone()));
+                entries.push((format!("{}.kind", name), mark.kind.as_str().to_string()));
+            }
+        }
+        for (k, v) in entries {
+            conf.with_section(Some(id)).set(k, v);
+        }
+    }
+}
+
+/// Watermarks by the same id as checkpoints, saved to `watermark.ini` on
+/// every change. Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Watermarks(Arc<Mutex<HashMap<String, Watermark>>>);
+
+impl Watermarks {
+    pub fn load() -> Self {
+        let mut map = HashMap::new();
+        if let Ok(conf) = Ini::load_from_file(FILE) {
+            for (id, inf) in conf.iter() {
+                if let (Some(id), Some(watermark)) = (id, Watermark::parse(inf)) {
+                    map.insert(id.to_string(), watermark);
+                }
+            }
+        }
+        Self(Arc::new(Mutex::new(map)))
+    }
+
+    pub fn get(&self, id: &str) -> Option<Watermark> {
+        self.0.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn set(&self, id: &str, watermark: Watermark) -> Result<()> {
+        let mut map = self.0.lock().unwrap();
+        map.insert(id.to_string(), watermark);
+        save(&map)
+    }
+
+    /// Moves the watermark up to the pending mark once the copy finished.
+    pub fn commit(&self, id: &str) -> Result<()> {
+        let mut map = self.0.lock().unwrap();
+        if let Some(watermark) = map.get_mut(id) {
+            if let Some(pending) = watermark.pending.take() {
+                watermark.value = Some(pending);
+            }
+        }
+        save(&map)
+    }
+}
+
+/// Rewrites the whole file through a temporary one, like `checkpoint.ini`.
+fn save(map: &HashMap<String, Watermark>) -> Result<()> {
+    let mut conf = Ini::new();
+    for (id, watermark) in map {
+        watermark.write(&mut conf, id);
+    }
+    let tmp = format!("{}.tmp", FILE);
+    conf.write_to_file(&tmp)
+        .and_then(|_| std::fs::rename(&tmp, FILE))
+        .map_err(|e| Error::Checkpoint(format!("{}: {}", FILE, e)))
+}